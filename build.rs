@@ -0,0 +1,15 @@
+// `cfg(sanitize)` is unstable, but cargo passes the target's cfgs to build
+// scripts on any toolchain, so the sanitizer hooks are gated on cfgs set
+// here. Without the sanitizer, the `asan` and `msan` features are no-ops
+// rather than link errors.
+fn main() {
+    println!("cargo::rerun-if-changed=build.rs");
+    let sanitizers = std::env::var("CARGO_CFG_SANITIZE").unwrap_or_default();
+    for sanitizer in sanitizers.split(',') {
+        match sanitizer {
+            "address" => println!("cargo::rustc-cfg=sanitize_address"),
+            "memory" => println!("cargo::rustc-cfg=sanitize_memory"),
+            _ => {}
+        }
+    }
+}
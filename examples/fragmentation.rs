@@ -0,0 +1,274 @@
+//! Simulates allocation workloads against each allocator and prints how much
+//! of the heap is wasted over time, as CSV on stdout.
+//!
+//! Usage: cargo run --example fragmentation [--allocator name] [workload]
+//! [steps] [sample_every]
+//!
+//! `workload` is one of `web-server`, `parser`, `embedded` or `all` (default).
+//!
+//! `--allocator` picks one of `bump`, `heap`, `linked_list_allocator` or
+//! `talc`; by default all of them run. `linked_list_allocator` and `talc` run
+//! the same workloads for comparison.
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::env;
+
+use simple_alloc::{AnyAllocator, BumpAllocator, Heap};
+
+#[path = "../tests/adapters/mod.rs"]
+mod adapters;
+
+const HEAP_SIZE: usize = 1 << 20;
+
+#[derive(Clone, Copy)]
+enum Workload {
+    /// Short-lived per-request buffers freed together, plus a slowly
+    /// churning cache of long-lived entries.
+    WebServer,
+    /// Many small AST-like nodes and a growing token buffer, all dropped at
+    /// the end of each document.
+    Parser,
+    /// A fixed set of message sizes churned around a constant live count.
+    Embedded,
+}
+
+impl Workload {
+    const ALL: [Workload; 3] = [Workload::WebServer, Workload::Parser, Workload::Embedded];
+
+    fn name(self) -> &'static str {
+        match self {
+            Workload::WebServer => "web-server",
+            Workload::Parser => "parser",
+            Workload::Embedded => "embedded",
+        }
+    }
+}
+
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+/// Tracks live blocks and the span of heap addresses handed out so far,
+/// which is the footprint for allocators that manage a single region.
+struct Simulation<'a, A> {
+    allocator: &'a A,
+    live: Vec<(*mut u8, Layout)>,
+    live_bytes: usize,
+    low: usize,
+    high: usize,
+    failed: usize,
+}
+
+impl<'a, A: GlobalAlloc> Simulation<'a, A> {
+    fn new(allocator: &'a A) -> Self {
+        Self {
+            allocator,
+            live: Vec::new(),
+            live_bytes: 0,
+            low: usize::MAX,
+            high: 0,
+            failed: 0,
+        }
+    }
+
+    fn alloc(&mut self, size: usize, align: usize) -> Option<usize> {
+        let layout = Layout::from_size_align(size, align).unwrap();
+        let ptr = unsafe { self.allocator.alloc(layout) };
+        if ptr.is_null() {
+            self.failed += 1;
+            return None;
+        }
+        self.low = self.low.min(ptr.addr());
+        self.high = self.high.max(ptr.addr() + size);
+        self.live_bytes += size;
+        self.live.push((ptr, layout));
+        Some(self.live.len() - 1)
+    }
+
+    fn free(&mut self, index: usize) {
+        let (ptr, layout) = self.live.swap_remove(index);
+        self.live_bytes -= layout.size();
+        unsafe { self.allocator.dealloc(ptr, layout) };
+    }
+
+    fn free_from(&mut self, first: usize) {
+        while self.live.len() > first {
+            self.free(self.live.len() - 1);
+        }
+    }
+
+    fn grow(&mut self, index: usize, new_size: usize) {
+        let (ptr, layout) = self.live[index];
+        let new_ptr = unsafe { self.allocator.realloc(ptr, layout, new_size) };
+        if new_ptr.is_null() {
+            self.failed += 1;
+            return;
+        }
+        self.high = self.high.max(new_ptr.addr() + new_size);
+        self.live_bytes = self.live_bytes - layout.size() + new_size;
+        self.live[index] = (
+            new_ptr,
+            Layout::from_size_align(new_size, layout.align()).unwrap(),
+        );
+    }
+
+    fn step(&mut self, workload: Workload, rng: &mut Rng) {
+        match workload {
+            Workload::WebServer => {
+                // keep a cache of up to 64 long-lived entries at the front
+                let cache = self.live.len().min(64);
+                if cache == 64 && rng.below(4) == 0 {
+                    self.free(rng.below(cache));
+                }
+                if self.live.len() < 64 {
+                    self.alloc(256 + rng.below(2048), 8);
+                }
+                let first = self.live.len();
+                for _ in 0..1 + rng.below(16) {
+                    self.alloc(16 + rng.below(4096), 8);
+                }
+                self.free_from(first);
+            }
+            Workload::Parser => {
+                let first = self.live.len();
+                let Some(tokens) = self.alloc(64, 8) else {
+                    return;
+                };
+                let mut capacity = 64;
+                for _ in 0..32 + rng.below(256) {
+                    self.alloc(24 + 8 * rng.below(6), 8);
+                    if rng.below(8) == 0 {
+                        capacity *= 2;
+                        self.grow(tokens, capacity.min(16384));
+                    }
+                }
+                self.free_from(first);
+            }
+            Workload::Embedded => {
+                const SIZES: [usize; 4] = [32, 64, 128, 512];
+                while self.live.len() < 128 {
+                    if self.alloc(SIZES[rng.below(SIZES.len())], 4).is_none() {
+                        break;
+                    }
+                }
+                for _ in 0..8 {
+                    if !self.live.is_empty() {
+                        self.free(rng.below(self.live.len()));
+                    }
+                }
+            }
+        }
+    }
+
+    fn report(&self, allocator: &str, workload: Workload, step: usize) {
+        let span = self.high.saturating_sub(self.low);
+        println!(
+            "{allocator},{},{step},{},{},{span},{},{}",
+            workload.name(),
+            self.live_bytes,
+            self.live.len(),
+            span.saturating_sub(self.live_bytes),
+            self.failed,
+        );
+    }
+
+    fn finish(mut self) {
+        self.free_from(0);
+    }
+}
+
+fn simulate<A: GlobalAlloc>(
+    name: &str,
+    allocator: &A,
+    workload: Workload,
+    steps: usize,
+    sample_every: usize,
+) {
+    let mut rng = Rng(12345);
+    let mut simulation = Simulation::new(allocator);
+    for step in 0..steps {
+        simulation.step(workload, &mut rng);
+        if step % sample_every == 0 || step + 1 == steps {
+            simulation.report(name, workload, step);
+        }
+    }
+    simulation.finish();
+}
+
+const ALLOCATORS: [&str; 4] = ["bump", "heap", "linked_list_allocator", "talc"];
+
+/// A fresh allocator with a `HEAP_SIZE` heap. Its memory is leaked, which
+/// is fine for a one-shot run.
+fn by_name(name: &str) -> Box<AnyAllocator<HEAP_SIZE>> {
+    Box::new(match name {
+        "bump" => AnyAllocator::Bump(BumpAllocator::new([0; HEAP_SIZE])),
+        "heap" => {
+            let heap = Heap::empty();
+            let memory = Box::leak(vec![0u8; HEAP_SIZE].into_boxed_slice());
+            unsafe { heap.init(memory.as_mut_ptr().expose_provenance(), HEAP_SIZE) };
+            AnyAllocator::Heap(heap)
+        }
+        "linked_list_allocator" => AnyAllocator::Other(Box::leak(Box::new(
+            adapters::LinkedListAllocator::new(HEAP_SIZE),
+        ))),
+        "talc" => AnyAllocator::Other(Box::leak(Box::new(adapters::TalcAllocator::new(HEAP_SIZE)))),
+        _ => unreachable!(),
+    })
+}
+
+fn main() {
+    let mut args: Vec<String> = env::args().skip(1).collect();
+    let allocators = match args.iter().position(|arg| arg == "--allocator") {
+        None => ALLOCATORS.to_vec(),
+        Some(flag) => {
+            let name = args.get(flag + 1).cloned().unwrap_or_default();
+            args.drain(flag..(flag + 2).min(args.len()));
+            match ALLOCATORS.iter().find(|&&a| a == name) {
+                Some(&allocator) => vec![allocator],
+                None => {
+                    eprintln!("unknown allocator {name:?}, expected one of {ALLOCATORS:?}");
+                    std::process::exit(2);
+                }
+            }
+        }
+    };
+    let mut args = args.into_iter();
+    let workloads = match args.next().as_deref() {
+        None | Some("all") => Workload::ALL.to_vec(),
+        Some(name) => match Workload::ALL.iter().find(|w| w.name() == name) {
+            Some(&workload) => vec![workload],
+            None => {
+                eprintln!(
+                    "unknown workload {name:?}, expected web-server, parser, embedded or all"
+                );
+                std::process::exit(2);
+            }
+        },
+    };
+    let steps = args
+        .next()
+        .map_or(1000, |s| s.parse().expect("steps must be a number"));
+    let sample_every = args
+        .next()
+        .map_or(50, |s| s.parse().expect("sample_every must be a number"))
+        .max(1);
+
+    println!("allocator,workload,step,live_bytes,live_blocks,heap_span,waste_bytes,failed_allocs");
+    for workload in workloads {
+        for &name in &allocators {
+            let allocator = by_name(name);
+            simulate(name, &*allocator, workload, steps, sample_every);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Measures what latency statistics cost a multithreaded workload, with the
+//! counters sharded per thread as in `Timed` and with a single shared set of
+//! counters, and prints the throughput of each as CSV on stdout.
+//!
+//! Usage: cargo run --release --features latency-stats --example stats_contention [threads] [ops]
+//!
+//! `ops` is the number of alloc/dealloc pairs per thread.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::env;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+use simple_alloc::{Operation, Timed};
+
+/// The straightforward alternative to `Timed`: one histogram per operation,
+/// shared by every thread.
+struct Unsharded<A> {
+    inner: A,
+    buckets: [[AtomicU64; 64]; 2],
+}
+
+impl<A> Unsharded<A> {
+    fn new(inner: A) -> Self {
+        Self {
+            inner,
+            buckets: [const { [const { AtomicU64::new(0) }; 64] }; 2],
+        }
+    }
+
+    fn time<R>(&self, operation: usize, f: impl FnOnce() -> R) -> R {
+        let start = Instant::now();
+        let result = f();
+        let nanos = start.elapsed().as_nanos() as u64;
+        let bucket = (u64::BITS - nanos.leading_zeros()) as usize;
+        self.buckets[operation][bucket.min(63)].fetch_add(1, Ordering::Relaxed);
+        result
+    }
+
+    fn count(&self, operation: usize) -> u64 {
+        self.buckets[operation]
+            .iter()
+            .map(|n| n.load(Ordering::Relaxed))
+            .sum()
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Unsharded<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.time(0, || unsafe { self.inner.alloc(layout) })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.time(1, || unsafe { self.inner.dealloc(ptr, layout) })
+    }
+}
+
+/// Runs `ops` alloc/dealloc pairs on each of `threads` threads and returns
+/// the throughput in millions of pairs per second.
+fn run(allocator: &(impl GlobalAlloc + Sync), threads: usize, ops: usize) -> f64 {
+    let layout = Layout::from_size_align(32, 8).unwrap();
+    let start = Instant::now();
+    std::thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                for _ in 0..ops {
+                    unsafe {
+                        let ptr = allocator.alloc(layout);
+                        assert!(!ptr.is_null());
+                        allocator.dealloc(std::hint::black_box(ptr), layout);
+                    }
+                }
+            });
+        }
+    });
+    (threads * ops) as f64 / start.elapsed().as_secs_f64() / 1e6
+}
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let threads = args.next().map_or_else(
+        || std::thread::available_parallelism().map_or(4, |n| n.get()),
+        |s| s.parse().expect("threads must be a number"),
+    );
+    let ops = args
+        .next()
+        .map_or(1_000_000, |s| s.parse().expect("ops must be a number"));
+
+    println!("counters,threads,mops_per_sec");
+    println!("none,{threads},{:.2}", run(&System, threads, ops));
+
+    let unsharded = Unsharded::new(System);
+    let mops = run(&unsharded, threads, ops);
+    assert_eq!(unsharded.count(0), unsharded.count(1));
+    println!("unsharded,{threads},{mops:.2}");
+
+    let sharded = Timed::new(System);
+    let mops = run(&sharded, threads, ops);
+    assert_eq!(
+        sharded.latency(Operation::Alloc).count(),
+        (threads * ops) as u64
+    );
+    println!("sharded,{threads},{mops:.2}");
+}
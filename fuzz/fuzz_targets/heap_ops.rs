@@ -0,0 +1,16 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use simple_alloc::test_utils::{HeapOp, run_heap_ops};
+use simple_alloc::{BumpAllocator, Passthrough};
+
+fuzz_target!(|ops: Vec<HeapOp>| {
+    let allocator = BumpAllocator::new([0; 65536]);
+    run_heap_ops(&allocator, &ops);
+
+    let allocator = Passthrough::<_, _, 1024>::new(
+        BumpAllocator::new([0; 16384]),
+        BumpAllocator::new([0; 65536]),
+    );
+    run_heap_ops(&allocator, &ops);
+});
@@ -0,0 +1,208 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+use crate::zero_size;
+
+/// Wraps an allocator and records when each block was allocated, counted in
+/// allocator operations, so that blocks surviving far longer than the
+/// steady-state workload can be listed with
+/// [`report_older_than`](Self::report_older_than). This is how slow leaks in
+/// long-running firmware are usually found.
+///
+/// Every `alloc` and `dealloc` advances the clock by one. Live blocks are
+/// kept in a list threaded through a header in front of each block, which
+/// costs four words per allocation, and a spinlock serializes updates to
+/// it. `realloc` moves the block, so it counts as a new allocation.
+#[derive(Debug)]
+pub struct Aged<A> {
+    inner: A,
+    clock: AtomicU64,
+    locked: AtomicBool,
+    live: UnsafeCell<*mut AgeHeader>,
+}
+
+unsafe impl<A: Sync> Sync for Aged<A> {}
+unsafe impl<A: Send> Send for Aged<A> {}
+
+#[repr(C)]
+struct AgeHeader {
+    prev: *mut AgeHeader,
+    next: *mut AgeHeader,
+    birth: u64,
+    size: usize,
+}
+
+/// A live block listed by [`Aged::report_older_than`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LiveAllocation {
+    pub ptr: *mut u8,
+    pub size: usize,
+    /// Operations since the block was allocated.
+    pub age: u64,
+}
+
+/// The layout of a block with its header in front, and the offset of the
+/// user's block in it. The header ends right where the user's block starts.
+fn with_header(layout: Layout) -> Option<(Layout, usize)> {
+    Layout::new::<AgeHeader>().extend(layout).ok()
+}
+
+fn header_of(ptr: *mut u8) -> *mut AgeHeader {
+    ptr.wrapping_sub(size_of::<AgeHeader>()).cast()
+}
+
+impl<A> Aged<A> {
+    pub const fn new(inner: A) -> Self {
+        Self {
+            inner,
+            clock: AtomicU64::new(0),
+            locked: AtomicBool::new(false),
+            live: UnsafeCell::new(ptr::null_mut()),
+        }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Operations performed so far.
+    pub fn clock(&self) -> u64 {
+        self.clock.load(Ordering::Relaxed)
+    }
+
+    /// Calls `f` with every live block allocated more than `ops` operations
+    /// ago, newest first. The list is locked meanwhile, so `f` must not
+    /// allocate from or free to this allocator.
+    pub fn report_older_than(&self, ops: u64, mut f: impl FnMut(LiveAllocation)) {
+        self.with_list(|live| {
+            let now = self.clock();
+            let mut header = *live;
+            while !header.is_null() {
+                let AgeHeader {
+                    next, birth, size, ..
+                } = unsafe { header.read() };
+                let age = now - birth;
+                if age > ops {
+                    f(LiveAllocation {
+                        ptr: header.wrapping_add(1).cast(),
+                        size,
+                        age,
+                    });
+                }
+                header = next;
+            }
+        });
+    }
+
+    fn with_list<R>(&self, f: impl FnOnce(&mut *mut AgeHeader) -> R) -> R {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        let result = f(unsafe { &mut *self.live.get() });
+        self.locked.store(false, Ordering::Release);
+        result
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Aged<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == 0 {
+            return zero_size::dangling(layout);
+        }
+        let Some((outer, offset)) = with_header(layout) else {
+            return ptr::null_mut();
+        };
+        let block = unsafe { self.inner.alloc(outer) };
+        if block.is_null() {
+            return block;
+        }
+        let birth = self.clock.fetch_add(1, Ordering::Relaxed);
+        let ptr = unsafe { block.add(offset) };
+        let header = header_of(ptr);
+        self.with_list(|live| unsafe {
+            header.write(AgeHeader {
+                prev: ptr::null_mut(),
+                next: *live,
+                birth,
+                size: layout.size(),
+            });
+            if !live.is_null() {
+                (**live).prev = header;
+            }
+            *live = header;
+        });
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if zero_size::is_dangling(ptr, layout) {
+            return;
+        }
+        self.clock.fetch_add(1, Ordering::Relaxed);
+        let header = header_of(ptr);
+        self.with_list(|live| unsafe {
+            let AgeHeader { prev, next, .. } = header.read();
+            if prev.is_null() {
+                *live = next;
+            } else {
+                (*prev).next = next;
+            }
+            if !next.is_null() {
+                (*next).prev = prev;
+            }
+        });
+        let (outer, offset) = with_header(layout).unwrap();
+        unsafe { self.inner.dealloc(ptr.sub(offset), outer) };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::BumpAllocator;
+
+    test_suite! {
+        Aged::new(BumpAllocator::new([0; 65536])),
+        Aged::new(BumpAllocator::new([0; 256]))
+    }
+
+    #[test]
+    fn test_report_older_than() {
+        let allocator = Aged::new(BumpAllocator::new([0; 4096]));
+        let layout = Layout::from_size_align(48, 16).unwrap();
+
+        unsafe {
+            let leaked = allocator.alloc(layout);
+            let freed = allocator.alloc(layout);
+            // the steady-state workload
+            for _ in 0..10 {
+                let ptr = allocator.alloc(Layout::new::<u64>());
+                allocator.dealloc(ptr, Layout::new::<u64>());
+            }
+            allocator.dealloc(freed, layout);
+            let recent = allocator.alloc(Layout::new::<u32>());
+            assert_eq!(allocator.clock(), 24);
+
+            let mut old = std::vec::Vec::new();
+            allocator.report_older_than(5, |allocation| old.push(allocation));
+            assert_eq!(
+                old,
+                [LiveAllocation {
+                    ptr: leaked,
+                    size: 48,
+                    age: 24,
+                }]
+            );
+
+            let mut all = std::vec::Vec::new();
+            allocator.report_older_than(0, |allocation| all.push(allocation.ptr));
+            assert_eq!(all, [recent, leaked]);
+        }
+    }
+}
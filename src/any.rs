@@ -0,0 +1,148 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+
+use crate::{BumpAllocator, Heap};
+
+/// One of the crate's allocators, picked at runtime, e.g. from a command-line
+/// flag in a benchmark or a test harness, instead of compiling one build per
+/// allocator.
+///
+/// `Other` takes any other allocator, such as a wrapped one (`Locked`,
+/// `Passthrough`, ...) or a third-party allocator used for comparison. The
+/// wrappers are generic, so they don't get variants of their own.
+/// `MonotonicResource` and `UnsyncPoolResource` can't be held at all: they
+/// are not `Sync`, and an `AnyAllocator` has to be to serve as the global
+/// allocator. Dispatch is a `match` on every call.
+pub enum AnyAllocator<const HEAP_SIZE: usize> {
+    Bump(BumpAllocator<HEAP_SIZE>),
+    Heap(Heap),
+    #[cfg(all(feature = "std", unix))]
+    SharedMem(crate::SharedMemAllocator),
+    #[cfg(all(feature = "std", unix))]
+    FileArena(crate::FileArena),
+    Other(&'static (dyn GlobalAlloc + Sync)),
+}
+
+impl<const HEAP_SIZE: usize> AnyAllocator<HEAP_SIZE> {
+    /// A short name for the strategy, for reports.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AnyAllocator::Bump(_) => "bump",
+            AnyAllocator::Heap(_) => "heap",
+            #[cfg(all(feature = "std", unix))]
+            AnyAllocator::SharedMem(_) => "shared_mem",
+            #[cfg(all(feature = "std", unix))]
+            AnyAllocator::FileArena(_) => "file_arena",
+            AnyAllocator::Other(_) => "other",
+        }
+    }
+
+    fn get(&self) -> &dyn GlobalAlloc {
+        match self {
+            AnyAllocator::Bump(allocator) => allocator,
+            AnyAllocator::Heap(allocator) => allocator,
+            #[cfg(all(feature = "std", unix))]
+            AnyAllocator::SharedMem(allocator) => allocator,
+            #[cfg(all(feature = "std", unix))]
+            AnyAllocator::FileArena(allocator) => allocator,
+            AnyAllocator::Other(allocator) => *allocator,
+        }
+    }
+}
+
+impl<const HEAP_SIZE: usize> fmt::Debug for AnyAllocator<HEAP_SIZE> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AnyAllocator::Bump(allocator) => f.debug_tuple("Bump").field(allocator).finish(),
+            AnyAllocator::Heap(allocator) => f.debug_tuple("Heap").field(allocator).finish(),
+            #[cfg(all(feature = "std", unix))]
+            AnyAllocator::SharedMem(allocator) => {
+                f.debug_tuple("SharedMem").field(allocator).finish()
+            }
+            #[cfg(all(feature = "std", unix))]
+            AnyAllocator::FileArena(allocator) => {
+                f.debug_tuple("FileArena").field(allocator).finish()
+            }
+            AnyAllocator::Other(_) => f.debug_tuple("Other").finish_non_exhaustive(),
+        }
+    }
+}
+
+unsafe impl<const HEAP_SIZE: usize> GlobalAlloc for AnyAllocator<HEAP_SIZE> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        unsafe { self.get().alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.get().dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        unsafe { self.get().realloc(ptr, layout, new_size) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    extern crate std;
+    use std::boxed::Box;
+
+    /// Builds the allocator named `name`, as a command-line tool would.
+    fn by_name<const HEAP_SIZE: usize>(name: &str) -> AnyAllocator<HEAP_SIZE> {
+        match name {
+            "bump" => AnyAllocator::Bump(BumpAllocator::new([0; HEAP_SIZE])),
+            "heap" => {
+                let heap = Heap::empty();
+                let region = Box::leak(Box::new([0u8; HEAP_SIZE]));
+                unsafe { heap.init(region.as_mut_ptr().expose_provenance(), HEAP_SIZE) };
+                AnyAllocator::Heap(heap)
+            }
+            "other" => AnyAllocator::Other(Box::leak(Box::new(BumpAllocator::new([0; HEAP_SIZE])))),
+            _ => panic!("unknown allocator {name:?}"),
+        }
+    }
+
+    mod heap {
+        use super::*;
+
+        test_suite! {
+            by_name::<65536>("heap"),
+            by_name::<256>("heap")
+        }
+    }
+
+    mod bump {
+        use super::*;
+
+        test_suite! {
+            by_name::<65536>("bump"),
+            by_name::<256>("bump")
+        }
+    }
+
+    mod other {
+        use super::*;
+
+        test_suite! {
+            by_name::<65536>("other"),
+            by_name::<256>("other")
+        }
+    }
+
+    #[test]
+    fn test_dispatch() {
+        for name in ["bump", "heap", "other"] {
+            let allocator = by_name::<256>(name);
+            assert_eq!(allocator.name(), name);
+            let layout = Layout::from_size_align(200, 8).unwrap();
+            unsafe {
+                let ptr = allocator.alloc(layout);
+                assert!(!ptr.is_null());
+                // the heap behind it is the one of the chosen strategy
+                assert!(allocator.alloc(layout).is_null());
+                allocator.dealloc(ptr, layout);
+            }
+        }
+    }
+}
@@ -0,0 +1,156 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::{MemoryResource, zero_size};
+
+/// A bump arena carved out of a block of a parent allocator or
+/// [`MemoryResource`].
+///
+/// The arena reserves `size` bytes from its parent up front and never grows,
+/// which gives a subsystem a hard memory budget inside a shared heap. The
+/// block goes back to the parent when the arena is dropped. Arenas can be
+/// nested, since an arena is itself a `GlobalAlloc`.
+///
+/// Like [`BumpAllocator`](crate::BumpAllocator), `dealloc` never frees.
+#[derive(Debug)]
+pub struct Arena<'a, P: MemoryResource + ?Sized> {
+    parent: &'a P,
+    block: *mut u8,
+    layout: Layout,
+    next_free: AtomicUsize,
+}
+
+unsafe impl<P: MemoryResource + Sync + ?Sized> Send for Arena<'_, P> {}
+unsafe impl<P: MemoryResource + Sync + ?Sized> Sync for Arena<'_, P> {}
+
+// Same guarantee as BumpAllocator's heap.
+const BLOCK_ALIGN: usize = 16;
+
+impl<'a, P: MemoryResource + ?Sized> Arena<'a, P> {
+    /// Reserves `size` bytes from `parent`, or returns `None` if the parent
+    /// is out of memory.
+    pub fn new_in(parent: &'a P, size: usize) -> Option<Self> {
+        let layout = Layout::from_size_align(size, BLOCK_ALIGN).ok()?;
+        let block = parent.allocate(layout);
+        if block.is_null() {
+            return None;
+        }
+        Some(Self {
+            parent,
+            block,
+            layout,
+            next_free: AtomicUsize::new(0),
+        })
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.layout.size()
+    }
+
+    pub fn used(&self) -> usize {
+        self.next_free.load(Ordering::Acquire)
+    }
+}
+
+impl<P: MemoryResource + ?Sized> Drop for Arena<'_, P> {
+    fn drop(&mut self) {
+        unsafe { self.parent.deallocate(self.block, self.layout) };
+    }
+}
+
+unsafe impl<P: MemoryResource + ?Sized> GlobalAlloc for Arena<'_, P> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == 0 {
+            return zero_size::dangling(layout);
+        }
+        let base = self.block.addr();
+        let mut block_start = 0;
+        let result = self
+            .next_free
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |next| {
+                // align the address, alignments above the block's own need
+                // padding
+                let start = (base + next).next_multiple_of(layout.align()) - base;
+                let end = start.checked_add(layout.size())?;
+                if end > self.capacity() {
+                    return None;
+                }
+                block_start = start;
+                Some(end)
+            });
+        if result.is_err() {
+            return ptr::null_mut();
+        }
+        unsafe { self.block.add(block_start) }
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::BumpAllocator;
+    use crate::test_utils::Counting;
+    use std::boxed::Box;
+
+    fn leaked_parent() -> &'static BumpAllocator<{ 1 << 18 }> {
+        Box::leak(Box::new(BumpAllocator::new([0; 1 << 18])))
+    }
+
+    test_suite! {
+        Arena::new_in(leaked_parent(), 65536).unwrap(),
+        Arena::new_in(leaked_parent(), 256).unwrap()
+    }
+
+    #[test]
+    fn test_child_arenas_budget_and_release() {
+        let parent = Counting::new();
+        let layout = Layout::from_size_align(64, 8).unwrap();
+
+        {
+            let network = Arena::new_in(&parent, 1024).unwrap();
+            let nested = Arena::new_in(&network, 256).unwrap();
+            assert_eq!(parent.live(), 1024);
+
+            unsafe {
+                // the nested arena runs out at its own budget, not the parent's
+                for _ in 0..4 {
+                    assert!(!nested.alloc(layout).is_null());
+                }
+                assert!(nested.alloc(layout).is_null());
+                assert_eq!(network.used(), 256);
+                assert!(!network.alloc(layout).is_null());
+            }
+        }
+
+        assert_eq!(parent.live(), 0);
+    }
+
+    #[test]
+    fn test_upstream_chosen_at_runtime() {
+        let bump = BumpAllocator::new([0; 1024]);
+        let system = Counting::new();
+
+        for use_system in [false, true] {
+            let upstream: &dyn MemoryResource = if use_system { &system } else { &bump };
+            let arena = Arena::new_in(upstream, 512).unwrap();
+            unsafe {
+                assert!(
+                    !arena
+                        .alloc(Layout::from_size_align(512, 1).unwrap())
+                        .is_null()
+                );
+            }
+        }
+        assert_eq!(system.live(), 0);
+    }
+
+    #[test]
+    fn test_new_in_fails_when_parent_is_full() {
+        let parent = BumpAllocator::new([0; 256]);
+        assert!(Arena::new_in(&parent, 512).is_none());
+        assert!(Arena::new_in(&parent, 128).is_some());
+    }
+}
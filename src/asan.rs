@@ -0,0 +1,63 @@
+//! Manual AddressSanitizer poisoning, so ASan reports accesses to memory
+//! inside our heaps that is not currently allocated.
+//!
+//! The hooks only do something when the crate is built with
+//! `RUSTFLAGS="-Zsanitizer=address"` on nightly, which sets
+//! `cfg(sanitize_address)` from the build script. Otherwise they are no-ops,
+//! so enabling the feature on its own builds and links fine.
+
+use core::sync::atomic::{AtomicU8, Ordering};
+
+#[cfg(sanitize_address)]
+unsafe extern "C" {
+    fn __asan_poison_memory_region(addr: *const u8, size: usize);
+    fn __asan_unpoison_memory_region(addr: *const u8, size: usize);
+}
+
+pub(crate) fn poison(_ptr: *const u8, _size: usize) {
+    #[cfg(sanitize_address)]
+    unsafe {
+        __asan_poison_memory_region(_ptr, _size)
+    }
+}
+
+pub(crate) fn unpoison(_ptr: *const u8, _size: usize) {
+    #[cfg(sanitize_address)]
+    unsafe {
+        __asan_unpoison_memory_region(_ptr, _size)
+    }
+}
+
+/// Poisons a whole heap exactly once, before the first block is unpoisoned.
+#[derive(Debug)]
+pub(crate) struct PoisonOnce(AtomicU8);
+
+const UNPOISONED: u8 = 0;
+const POISONING: u8 = 1;
+const POISONED: u8 = 2;
+
+impl PoisonOnce {
+    pub(crate) const fn new() -> Self {
+        Self(AtomicU8::new(UNPOISONED))
+    }
+
+    pub(crate) fn poison(&self, heap: *const u8, size: usize) {
+        if self.0.load(Ordering::Acquire) == POISONED {
+            return;
+        }
+        match self
+            .0
+            .compare_exchange(UNPOISONED, POISONING, Ordering::Acquire, Ordering::Acquire)
+        {
+            Ok(_) => {
+                poison(heap, size);
+                self.0.store(POISONED, Ordering::Release);
+            }
+            Err(_) => {
+                while self.0.load(Ordering::Acquire) != POISONED {
+                    core::hint::spin_loop();
+                }
+            }
+        }
+    }
+}
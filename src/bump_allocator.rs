@@ -1,65 +1,409 @@
 
 use core::alloc::{GlobalAlloc, Layout};
 use core::cell::UnsafeCell;
+use core::fmt;
 use core::ptr;
-use core::sync::atomic::AtomicPtr;
 use core::sync::atomic::Ordering;
 
+use crate::OomPolicy;
+use crate::sync::{AtomicPtr, const_fn_unless_loom};
+use crate::zero_size;
 
+
+/// A bump allocator over a heap array of `HEAP_SIZE` bytes.
+///
+/// The heap is aligned to `A`, one of the `Align*` marker types, so requests
+/// up to that alignment need no padding at the start of the heap.
 #[derive(Debug)]
-#[repr(align(16))]
-pub struct BumpAllocator<const HEAP_SIZE: usize> {
-    heap: UnsafeCell<[u8; HEAP_SIZE]>,
+pub struct BumpAllocator<const HEAP_SIZE: usize, A = Align16> {
+    heap: Aligned<A, UnsafeCell<[u8; HEAP_SIZE]>>,
     next_free: AtomicPtr<u8>,
+    oom_policy: OomPolicy,
+    #[cfg(feature = "asan")]
+    asan_poisoned: crate::asan::PoisonOnce,
+    #[cfg(feature = "debug-oom")]
+    last_oom: crate::oom::LastOom,
 }
 
-unsafe impl<const HEAP_SIZE: usize> Sync for BumpAllocator<HEAP_SIZE> {}
+unsafe impl<const HEAP_SIZE: usize, A> Sync for BumpAllocator<HEAP_SIZE, A> {}
 
 impl<const HEAP_SIZE: usize> BumpAllocator<HEAP_SIZE> {
-    pub const fn new(array: [u8; HEAP_SIZE]) -> Self {
-        Self {
-            heap: UnsafeCell::new(array),
-            next_free: AtomicPtr::new(ptr::null_mut()),
+    const_fn_unless_loom! {
+        pub fn new(array: [u8; HEAP_SIZE]) -> Self {
+            Self::new_aligned(array)
         }
     }
+}
+
+impl<const HEAP_SIZE: usize, A> BumpAllocator<HEAP_SIZE, A> {
+    const_fn_unless_loom! {
+        /// Like [`new`](BumpAllocator::new), with the heap aligned to `A`
+        /// instead of 16 bytes.
+        pub fn new_aligned(array: [u8; HEAP_SIZE]) -> Self {
+            Self {
+                heap: Aligned {
+                    _align: [],
+                    value: UnsafeCell::new(array),
+                },
+                next_free: AtomicPtr::new(ptr::null_mut()),
+                oom_policy: OomPolicy::ReturnNull,
+                #[cfg(feature = "asan")]
+                asan_poisoned: crate::asan::PoisonOnce::new(),
+                #[cfg(feature = "debug-oom")]
+                last_oom: crate::oom::LastOom::new(),
+            }
+        }
+    }
+
+    /// Sets what `alloc` does when the heap is exhausted. Defaults to
+    /// [`OomPolicy::ReturnNull`].
+    pub const fn with_oom_policy(mut self, oom_policy: OomPolicy) -> Self {
+        self.oom_policy = oom_policy;
+        self
+    }
+
+    /// The most recent allocation this allocator failed, if any.
+    #[cfg(feature = "debug-oom")]
+    pub fn last_oom_info(&self) -> Option<crate::OomInfo> {
+        self.last_oom.get()
+    }
 
     fn heap_start(&self) -> *const u8 {
-        self.heap.get().cast()
+        self.heap.value.get().cast()
     }
-}
 
-fn align_up(ptr: *mut u8, alignment: usize) -> *mut u8 {
-    let mask = alignment - 1;
-    ((ptr.addr() + mask) & !mask) as *mut u8
-}
+    fn used(&self) -> usize {
+        // only the address is needed; callers that read the blocks below it
+        // synchronize with their writers themselves (see snapshot_into)
+        let next_free = self.next_free.load(Ordering::Relaxed);
+        if next_free.is_null() {
+            0
+        } else {
+            next_free.addr() - self.heap_start().addr()
+        }
+    }
 
-unsafe impl<const HEAP_SIZE: usize> GlobalAlloc for BumpAllocator<HEAP_SIZE> {
-    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+    /// How full the heap is, in thousandths.
+    pub fn occupancy_permille(&self) -> u32 {
+        (self.used() as u64 * 1000 / HEAP_SIZE.max(1) as u64) as u32
+    }
+
+    /// Size of the largest block with alignment `align` that an allocation
+    /// would succeed for right now, to decide whether to start some work
+    /// without attempting it. Other threads may allocate in the meantime.
+    ///
+    /// # Panics
+    ///
+    /// If `align` is not a power of two.
+    pub fn largest_allocatable(&self, align: usize) -> usize {
+        assert!(align.is_power_of_two(), "alignment must be a power of two");
+        let heap_start = self.heap_start().addr();
+        let next_free = heap_start + self.used();
+        (heap_start + HEAP_SIZE).saturating_sub(next_free.next_multiple_of(align))
+    }
+
+    /// Writes the allocator's state as a JSON object, for bug reports.
+    pub fn dump_state(&self, out: &mut impl fmt::Write) -> fmt::Result {
+        write!(
+            out,
+            r#"{{"allocator":"bump","capacity":{HEAP_SIZE},"used":{}}}"#,
+            self.used()
+        )
+    }
+
+    /// Copies the allocated part of the heap, and the allocation state, into
+    /// `snapshot`.
+    ///
+    /// With the `asan` feature, the copy reads blocks that were already freed,
+    /// so the whole allocated part is unpoisoned first: ASan no longer reports
+    /// uses of blocks freed before the snapshot.
+    ///
+    /// # Safety
+    ///
+    /// No other thread may allocate or write to memory from this allocator
+    /// while the snapshot is taken.
+    pub unsafe fn snapshot_into(&self, snapshot: &mut HeapSnapshot<HEAP_SIZE>) {
+        let used = self.used();
+        #[cfg(feature = "asan")]
+        crate::asan::unpoison(self.heap_start(), used);
+        unsafe {
+            ptr::copy_nonoverlapping(self.heap_start(), snapshot.heap.as_mut_ptr(), used);
+        }
+        snapshot.used = used;
+    }
+
+    /// Rolls the heap back to the state captured in `snapshot`: blocks
+    /// allocated since are released, and blocks that were live get their
+    /// contents back.
+    ///
+    /// # Safety
+    ///
+    /// No other thread may use the allocator during the restore, and no block
+    /// allocated after the snapshot was taken may be used afterwards.
+    pub unsafe fn restore(&self, snapshot: &HeapSnapshot<HEAP_SIZE>) {
+        let heap_start = self.heap_start().cast_mut();
+        // every block of the snapshot is usable again, and everything after
+        // it is free
+        #[cfg(feature = "asan")]
+        {
+            self.asan_poisoned.poison(heap_start, HEAP_SIZE);
+            crate::asan::unpoison(heap_start, snapshot.used);
+        }
+        unsafe {
+            ptr::copy_nonoverlapping(snapshot.heap.as_ptr(), heap_start, snapshot.used);
+            let next_free = heap_start.add(snapshot.used);
+            #[cfg(feature = "asan")]
+            crate::asan::poison(next_free, HEAP_SIZE - snapshot.used);
+            // the caller guarantees exclusive use, so nothing to publish
+            self.next_free.store(next_free, Ordering::Relaxed);
+        }
+    }
+
+    /// Takes space for `layout` out of the heap without handing it out yet,
+    /// or returns `None` if it doesn't fit.
+    ///
+    /// This is a single atomic update, so it is safe to call from an
+    /// interrupt handler. The OOM policy is not applied. The space is handed
+    /// out by [`commit`](Self::commit), possibly on another thread, or given
+    /// back by [`cancel`](Self::cancel).
+    pub fn reserve(&self, layout: Layout) -> Option<Reservation> {
+        if layout.size() == 0 {
+            return Some(Reservation {
+                ptr: zero_size::dangling(layout),
+                layout,
+            });
+        }
+        #[cfg(feature = "asan")]
+        self.asan_poisoned.poison(self.heap_start(), HEAP_SIZE);
+        let ptr = self.try_alloc(layout);
+        (!ptr.is_null()).then_some(Reservation { ptr, layout })
+    }
+
+    /// Turns a reservation from this allocator into an allocated block, to
+    /// be freed with `dealloc` and the reservation's layout.
+    pub fn commit(&self, reservation: Reservation) -> *mut u8 {
+        if reservation.layout.size() != 0 {
+            self.hand_out(reservation.ptr, reservation.layout);
+        }
+        reservation.ptr
+    }
+
+    /// Gives a reservation from this allocator back. The space is reused if
+    /// nothing was allocated after it, and lost otherwise.
+    pub fn cancel(&self, reservation: Reservation) {
+        if reservation.layout.size() == 0 {
+            return;
+        }
+        let end = reservation.ptr.wrapping_add(reservation.layout.size());
+        let _ = self.next_free.compare_exchange(
+            end,
+            reservation.ptr,
+            Ordering::Relaxed,
+            Ordering::Relaxed,
+        );
+    }
+
+    /// Grows the block at `ptr` by `additional` bytes without moving it,
+    /// which works when it is the last block allocated and the heap has room
+    /// after it. Returns whether it grew; on success the block must be freed
+    /// with its new size.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been allocated by this allocator with `old`, and not
+    /// freed since.
+    pub unsafe fn extend_in_place(&self, ptr: *mut u8, old: Layout, additional: usize) -> bool {
+        if old.size() == 0 {
+            return false;
+        }
+        let end = ptr.wrapping_add(old.size());
+        let heap_end = self.heap_start().addr() + HEAP_SIZE;
+        if additional > heap_end - end.addr() {
+            return false;
+        }
+        let new_end = end.wrapping_add(additional);
+        if self
+            .next_free
+            .compare_exchange(end, new_end, Ordering::Relaxed, Ordering::Relaxed)
+            .is_err()
+        {
+            return false;
+        }
+        #[cfg(feature = "valgrind")]
+        crate::valgrind::resizeinplace_block(ptr, old.size(), old.size() + additional);
+        #[cfg(feature = "asan")]
+        crate::asan::unpoison(end, additional);
+        #[cfg(feature = "msan")]
+        crate::msan::allocated(end, additional);
+        true
+    }
+
+    fn hand_out(&self, _ptr: *mut u8, _layout: Layout) {
+        #[cfg(feature = "valgrind")]
+        crate::valgrind::malloclike_block(_ptr, _layout.size());
+        #[cfg(feature = "asan")]
+        crate::asan::unpoison(_ptr, _layout.size());
+        #[cfg(feature = "msan")]
+        crate::msan::allocated(_ptr, _layout.size());
+    }
+
+    fn try_alloc(&self, layout: Layout) -> *mut u8 {
         let mut allocated_block_start = ptr::null_mut();
+        // Relaxed is enough: next_free only hands out disjoint ranges of
+        // addresses, which the total modification order of a single atomic
+        // guarantees on its own. No data is published through it; whatever a
+        // thread writes to its block reaches other threads through the
+        // pointer, which carries its own synchronization.
         let next_free =
             self.next_free
-                .fetch_update(Ordering::AcqRel, Ordering::Acquire, |next_free| {
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |next_free| {
                     let next_free = if next_free.is_null() {
                         self.heap_start().cast_mut()
                     } else {
                         next_free
                     };
                     let next_block_start = align_up(next_free, layout.align());
-                    let block_end = unsafe { next_block_start.add(layout.size()) };
-                    let heap_end = unsafe { self.heap_start().add(HEAP_SIZE) }.cast_mut();
-                    if block_end > heap_end {
+                    // check the fit on addresses, offsetting a pointer past
+                    // the end of the heap is undefined behavior
+                    let heap_end = self.heap_start().addr() + HEAP_SIZE;
+                    let available = heap_end.checked_sub(next_block_start.addr())?;
+                    if layout.size() > available {
                         return None;
                     }
                     allocated_block_start = next_block_start;
-                    Some(block_end)
+                    Some(unsafe { next_block_start.add(layout.size()) })
                 });
         if next_free.is_err() {
             return ptr::null_mut();
         }
         allocated_block_start
     }
+}
+
+/// Aligns `value` to the alignment of `A`.
+#[derive(Debug)]
+#[repr(C)]
+struct Aligned<A, T> {
+    _align: [A; 0],
+    value: T,
+}
+
+/// The default heap alignment of a [`BumpAllocator`].
+#[derive(Debug, Clone, Copy)]
+#[repr(align(16))]
+pub struct Align16;
+
+/// Cache-line alignment.
+#[derive(Debug, Clone, Copy)]
+#[repr(align(64))]
+pub struct Align64;
+
+/// Page alignment.
+#[derive(Debug, Clone, Copy)]
+#[repr(align(4096))]
+pub struct Align4096;
+
+/// Huge page alignment.
+#[derive(Debug, Clone, Copy)]
+#[repr(align(2097152))]
+pub struct Align2MiB;
+
+/// Space reserved in a [`BumpAllocator`] by
+/// [`reserve`](BumpAllocator::reserve), to be committed or cancelled on the
+/// same allocator. Dropping it leaks the space.
+#[must_use]
+#[derive(Debug)]
+pub struct Reservation {
+    ptr: *mut u8,
+    layout: Layout,
+}
+
+unsafe impl Send for Reservation {}
+
+impl Reservation {
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+}
+
+/// A copy of a [`BumpAllocator`]'s heap, taken with
+/// [`snapshot_into`](BumpAllocator::snapshot_into) and put back with
+/// [`restore`](BumpAllocator::restore).
+#[derive(Debug)]
+pub struct HeapSnapshot<const HEAP_SIZE: usize> {
+    heap: [u8; HEAP_SIZE],
+    used: usize,
+}
+
+impl<const HEAP_SIZE: usize> HeapSnapshot<HEAP_SIZE> {
+    pub const fn new() -> Self {
+        Self {
+            heap: [0; HEAP_SIZE],
+            used: 0,
+        }
+    }
+}
+
+impl<const HEAP_SIZE: usize> Default for HeapSnapshot<HEAP_SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Rounds `ptr` up to `alignment`, keeping its provenance so the result is
+/// still usable on strict-provenance targets such as CHERI.
+fn align_up(ptr: *mut u8, alignment: usize) -> *mut u8 {
+    let mask = alignment - 1;
+    ptr.map_addr(|addr| (addr + mask) & !mask)
+}
+
+unsafe impl<const HEAP_SIZE: usize, A> GlobalAlloc for BumpAllocator<HEAP_SIZE, A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == 0 {
+            return zero_size::dangling(layout);
+        }
+        #[cfg(feature = "asan")]
+        self.asan_poisoned.poison(self.heap_start(), HEAP_SIZE);
+        loop {
+            let ptr = self.try_alloc(layout);
+            if !ptr.is_null() {
+                self.hand_out(ptr, layout);
+                return ptr;
+            }
+            let used = self.used();
+            #[cfg(feature = "debug-oom")]
+            self.last_oom.record(crate::OomInfo {
+                layout,
+                used,
+                capacity: HEAP_SIZE,
+                largest_free: self.largest_allocatable(layout.align()),
+            });
+            if !self.oom_policy.should_retry(layout, used, HEAP_SIZE) {
+                return ptr;
+            }
+        }
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {
+        #[cfg(any(feature = "valgrind", feature = "asan", feature = "msan"))]
+        if !zero_size::is_dangling(_ptr, _layout) {
+            #[cfg(feature = "valgrind")]
+            crate::valgrind::freelike_block(_ptr);
+            #[cfg(feature = "asan")]
+            crate::asan::poison(_ptr, _layout.size());
+            #[cfg(feature = "msan")]
+            crate::msan::poison(_ptr, _layout.size());
+        }
+    }
+}
 
-    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
+// The heap may live on the stack, so leaving it poisoned would trip ASan on
+// whatever later reuses those bytes.
+#[cfg(feature = "asan")]
+impl<const HEAP_SIZE: usize, A> Drop for BumpAllocator<HEAP_SIZE, A> {
+    fn drop(&mut self) {
+        crate::asan::unpoison(self.heap_start(), HEAP_SIZE);
+    }
 }
 
 #[cfg(test)]
@@ -70,4 +414,194 @@ mod test {
         BumpAllocator::new([0; 65536]),
         BumpAllocator::new([0; 256])
 	}
+
+    #[test]
+    fn test_page_aligned_heap() {
+        let allocator = BumpAllocator::<8192, Align4096>::new_aligned([0; 8192]);
+        let page = Layout::from_size_align(4096, 4096).unwrap();
+
+        // no padding before the first page, so both fit
+        unsafe {
+            let first = allocator.alloc(page);
+            assert_eq!(first.cast_const(), allocator.heap_start());
+            assert!(!allocator.alloc(page).is_null());
+        }
+    }
+
+    #[test]
+    fn test_reserve_commit_cancel() {
+        let allocator = BumpAllocator::new([0; 256]);
+        let layout = Layout::from_size_align(64, 8).unwrap();
+
+        // the last reservation is given back on cancel
+        let reservation = allocator.reserve(layout).unwrap();
+        assert_eq!(allocator.used(), 64);
+        allocator.cancel(reservation);
+        assert_eq!(allocator.used(), 0);
+
+        // an earlier one can't be, but stays valid until committed
+        let first = allocator.reserve(layout).unwrap();
+        let second = allocator.reserve(layout).unwrap();
+        allocator.cancel(second);
+        let third = allocator.reserve(layout).unwrap();
+        assert_eq!(allocator.used(), 128);
+        allocator.cancel(first);
+        assert_eq!(allocator.used(), 128);
+
+        // reserved in one context, committed in another
+        let addr = std::thread::scope(|scope| {
+            scope
+                .spawn(|| {
+                    let ptr = allocator.commit(third);
+                    unsafe { ptr.write_bytes(0xEF, 64) };
+                    ptr.addr()
+                })
+                .join()
+                .unwrap()
+        });
+        assert_eq!(addr, allocator.heap_start().addr() + 64);
+
+        assert!(allocator.reserve(Layout::from_size_align(256, 1).unwrap()).is_none());
+        let empty = allocator.reserve(Layout::new::<()>()).unwrap();
+        assert_eq!(allocator.commit(empty), zero_size::dangling(Layout::new::<()>()));
+    }
+
+    #[test]
+    fn test_occupancy_and_largest_allocatable() {
+        let allocator = BumpAllocator::new([0; 256]);
+        assert_eq!(allocator.occupancy_permille(), 0);
+        assert_eq!(allocator.largest_allocatable(16), 256);
+
+        unsafe { allocator.alloc(Layout::from_size_align(100, 1).unwrap()) };
+        assert_eq!(allocator.occupancy_permille(), 390);
+        assert_eq!(allocator.largest_allocatable(1), 156);
+        assert_eq!(allocator.largest_allocatable(16), 144);
+        // only the heap's own alignment is known, so beyond it, it depends on
+        // where the heap landed
+        assert!(allocator.largest_allocatable(256) <= 144);
+
+        // the answer holds
+        let layout = Layout::from_size_align(144, 16).unwrap();
+        assert!(!unsafe { allocator.alloc(layout) }.is_null());
+        assert_eq!(allocator.largest_allocatable(16), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "alignment must be a power of two")]
+    fn test_largest_allocatable_rejects_zero_align() {
+        BumpAllocator::new([0; 256]).largest_allocatable(0);
+    }
+
+    #[test]
+    fn test_dump_state() {
+        let allocator = BumpAllocator::new([0; 256]);
+        unsafe { allocator.alloc(Layout::from_size_align(100, 1).unwrap()) };
+
+        let mut out = std::string::String::new();
+        allocator.dump_state(&mut out).unwrap();
+        assert_eq!(out, r#"{"allocator":"bump","capacity":256,"used":100}"#);
+    }
+
+    #[test]
+    fn test_snapshot_restore() {
+        let allocator = BumpAllocator::new([0; 1024]);
+        let mut snapshot = HeapSnapshot::new();
+
+        unsafe {
+            let layout = Layout::from_size_align(64, 8).unwrap();
+            let kept = allocator.alloc(layout);
+            kept.write_bytes(0xAA, 64);
+
+            allocator.snapshot_into(&mut snapshot);
+
+            // speculative work: overwrite the live block and fill the heap
+            kept.write_bytes(0xBB, 64);
+            let big = Layout::from_size_align(900, 8).unwrap();
+            assert!(!allocator.alloc(big).is_null());
+            assert!(allocator.alloc(big).is_null());
+
+            allocator.restore(&snapshot);
+
+            assert!(
+                std::slice::from_raw_parts(kept, 64)
+                    .iter()
+                    .all(|&b| b == 0xAA)
+            );
+            let again = allocator.alloc(big);
+            assert!(!again.is_null(), "space allocated after the snapshot was not released");
+            assert!(again as usize >= kept as usize + 64);
+        }
+    }
+
+    // Only catches anything when built with -Zsanitizer=address, where
+    // touching poisoned memory aborts the test.
+    #[cfg(feature = "asan")]
+    #[test]
+    fn test_snapshot_restore_with_freed_blocks() {
+        let allocator = BumpAllocator::new([0; 1024]);
+        let mut snapshot = HeapSnapshot::new();
+        let layout = Layout::from_size_align(128, 8).unwrap();
+
+        unsafe {
+            let freed = allocator.alloc(layout);
+            let kept = allocator.alloc(layout);
+            kept.write_bytes(0xAA, 128);
+            allocator.dealloc(freed, layout);
+
+            // copies the poisoned freed block along with the live one
+            allocator.snapshot_into(&mut snapshot);
+
+            kept.write_bytes(0xBB, 128);
+            allocator.dealloc(kept, layout);
+            allocator.restore(&snapshot);
+
+            // live at snapshot time, so usable again
+            assert!(
+                std::slice::from_raw_parts(kept, 128)
+                    .iter()
+                    .all(|&b| b == 0xAA)
+            );
+            kept.write_bytes(0xCC, 128);
+            allocator.dealloc(kept, layout);
+        }
+    }
+
+    #[cfg(feature = "debug-oom")]
+    #[test]
+    fn test_last_oom_info() {
+        let allocator = BumpAllocator::new([0; 256]);
+        assert_eq!(allocator.last_oom_info(), None);
+
+        unsafe {
+            assert!(!allocator.alloc(Layout::from_size_align(100, 1).unwrap()).is_null());
+            let layout = Layout::from_size_align(200, 8).unwrap();
+            assert!(allocator.alloc(layout).is_null());
+
+            let info = allocator.last_oom_info().unwrap();
+            assert_eq!(info.layout, layout);
+            assert_eq!(info.used, 100);
+            assert_eq!(info.capacity, 256);
+            // the padding needed to align the next block is not free space
+            assert_eq!(info.largest_free, allocator.largest_allocatable(8));
+            assert!((149..=156).contains(&info.largest_free));
+        }
+    }
+
+    #[test]
+    fn test_oom_policy_retry() {
+        use core::sync::atomic::AtomicUsize;
+
+        static CALLS: AtomicUsize = AtomicUsize::new(0);
+        fn handler(_layout: Layout) -> bool {
+            CALLS.fetch_add(1, Ordering::Relaxed) < 2
+        }
+
+        let allocator = BumpAllocator::new([0; 256]).with_oom_policy(OomPolicy::Retry(handler));
+
+        unsafe {
+            let ptr = allocator.alloc(Layout::from_size_align(512, 8).unwrap());
+            assert!(ptr.is_null());
+        }
+        assert_eq!(CALLS.load(Ordering::Relaxed), 3);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,203 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::ptr::{self, NonNull};
+
+use crate::BumpAllocator;
+
+/// A growable array in a [`BumpAllocator`], for building sequences of
+/// unknown length, such as a parser's token list.
+///
+/// While nothing else is allocated from the heap, growing extends the array
+/// in place (see [`BumpAllocator::extend_in_place`]) instead of copying it.
+/// Once the array is finished, [`into_slice`](Self::into_slice) turns it
+/// into a slice that lives as long as the allocator.
+#[derive(Debug)]
+pub struct BumpVec<'a, T, const HEAP_SIZE: usize, A = crate::Align16> {
+    allocator: &'a BumpAllocator<HEAP_SIZE, A>,
+    ptr: NonNull<T>,
+    len: usize,
+    capacity: usize,
+    _owns: PhantomData<T>,
+}
+
+const MIN_CAPACITY: usize = 4;
+
+impl<'a, T, const HEAP_SIZE: usize, A> BumpVec<'a, T, HEAP_SIZE, A> {
+    pub fn new_in(allocator: &'a BumpAllocator<HEAP_SIZE, A>) -> Self {
+        Self {
+            allocator,
+            ptr: NonNull::dangling(),
+            len: 0,
+            capacity: if size_of::<T>() == 0 { usize::MAX } else { 0 },
+            _owns: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Appends `value`, or gives it back if the heap is full.
+    pub fn push(&mut self, value: T) -> Result<(), T> {
+        if self.len == self.capacity && !self.grow() {
+            return Err(value);
+        }
+        unsafe { self.ptr.add(self.len).write(value) };
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        Some(unsafe { self.ptr.add(self.len).read() })
+    }
+
+    /// Hands the elements over to the allocator, keeping them alive as long
+    /// as it is.
+    pub fn into_slice(self) -> &'a mut [T] {
+        let this = core::mem::ManuallyDrop::new(self);
+        unsafe { core::slice::from_raw_parts_mut(this.ptr.as_ptr(), this.len) }
+    }
+
+    /// Doubles the capacity, in place if possible.
+    fn grow(&mut self) -> bool {
+        if self.capacity == 0 {
+            return self.reallocate(MIN_CAPACITY);
+        }
+        let Some(capacity) = self.capacity.checked_mul(2) else {
+            return false;
+        };
+        let old = Layout::array::<T>(self.capacity).unwrap();
+        if unsafe {
+            self.allocator
+                .extend_in_place(self.ptr.as_ptr().cast(), old, old.size())
+        } {
+            self.capacity = capacity;
+            return true;
+        }
+        self.reallocate(capacity)
+    }
+
+    fn reallocate(&mut self, capacity: usize) -> bool {
+        let Ok(layout) = Layout::array::<T>(capacity) else {
+            return false;
+        };
+        let Some(ptr) = NonNull::new(unsafe { self.allocator.alloc(layout) }) else {
+            return false;
+        };
+        let ptr = ptr.cast::<T>();
+        unsafe {
+            ptr::copy_nonoverlapping(self.ptr.as_ptr(), ptr.as_ptr(), self.len);
+            self.free();
+        }
+        self.ptr = ptr;
+        self.capacity = capacity;
+        true
+    }
+
+    /// Frees the buffer, without dropping the elements.
+    unsafe fn free(&mut self) {
+        if self.capacity != 0 && size_of::<T>() != 0 {
+            let layout = Layout::array::<T>(self.capacity).unwrap();
+            unsafe { self.allocator.dealloc(self.ptr.as_ptr().cast(), layout) };
+        }
+    }
+}
+
+impl<T, const HEAP_SIZE: usize, A> Deref for BumpVec<'_, T, HEAP_SIZE, A> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        unsafe { core::slice::from_raw_parts(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T, const HEAP_SIZE: usize, A> DerefMut for BumpVec<'_, T, HEAP_SIZE, A> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        unsafe { core::slice::from_raw_parts_mut(self.ptr.as_ptr(), self.len) }
+    }
+}
+
+impl<T, const HEAP_SIZE: usize, A> Drop for BumpVec<'_, T, HEAP_SIZE, A> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::drop_in_place(ptr::slice_from_raw_parts_mut(self.ptr.as_ptr(), self.len));
+            self.free();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    extern crate std;
+
+    use super::*;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_grows_in_place() {
+        let allocator = BumpAllocator::new([0; 4096]);
+        let mut tokens = BumpVec::new_in(&allocator);
+        tokens.push(0u32).unwrap();
+        let first = tokens.as_ptr();
+        for i in 1..100 {
+            tokens.push(i).unwrap();
+        }
+        // 4 * 2^5 elements, never copied
+        assert_eq!(tokens.as_ptr(), first);
+        assert_eq!(tokens.capacity(), 128);
+        let next = unsafe { allocator.alloc(Layout::new::<u8>()) };
+        assert_eq!(next.addr(), first.addr() + 128 * 4);
+
+        let tokens = tokens.into_slice();
+        assert!(tokens.iter().copied().eq(0..100));
+    }
+
+    #[test]
+    fn test_copies_when_not_on_top() {
+        let allocator = BumpAllocator::new([0; 4096]);
+        let mut a = BumpVec::new_in(&allocator);
+        let mut b = BumpVec::new_in(&allocator);
+        for i in 0..10u64 {
+            a.push(i).unwrap();
+            b.push(i * 2).unwrap();
+        }
+        assert!(a.iter().copied().eq(0..10));
+        assert!(b.iter().copied().eq((0..10).map(|i| i * 2)));
+        assert_eq!(a.pop(), Some(9));
+        assert_eq!(a.len(), 9);
+    }
+
+    #[test]
+    fn test_full_heap_and_drop() {
+        let allocator = BumpAllocator::new([0; 64]);
+        let value = Rc::new(());
+        let mut items = BumpVec::new_in(&allocator);
+        let mut pushed = 0;
+        while items.push(value.clone()).is_ok() {
+            pushed += 1;
+        }
+        assert_eq!(pushed, 64 / size_of::<Rc<()>>());
+        assert_eq!(Rc::strong_count(&value), pushed + 1);
+        drop(items);
+        assert_eq!(Rc::strong_count(&value), 1);
+
+        let mut units = BumpVec::new_in(&allocator);
+        for _ in 0..1000 {
+            units.push(()).unwrap();
+        }
+        assert_eq!(units.len(), 1000);
+    }
+}
@@ -0,0 +1,124 @@
+//! Cache-line-aligned allocation, for concurrent data structures whose
+//! blocks must not share a cache line with anything else (false sharing).
+//!
+//! [`CACHE_LINE`] follows the target: 128 bytes where the hardware fetches
+//! lines in pairs or has 128-byte lines, 256 bytes on s390x, and 64 bytes
+//! everywhere else. Targets with 32-byte lines get 64 too, which wastes some
+//! space but is never too small.
+
+use core::alloc::{GlobalAlloc, Layout, LayoutError};
+use core::ptr;
+
+use crate::zero_size;
+
+/// The alignment that keeps two blocks off the same cache line on this
+/// target.
+#[cfg(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "powerpc64",
+))]
+pub const CACHE_LINE: usize = 128;
+#[cfg(target_arch = "s390x")]
+pub const CACHE_LINE: usize = 256;
+#[cfg(not(any(
+    target_arch = "x86_64",
+    target_arch = "aarch64",
+    target_arch = "powerpc64",
+    target_arch = "s390x",
+)))]
+pub const CACHE_LINE: usize = 64;
+
+/// `layout` aligned to at least [`CACHE_LINE`], with its size rounded up to
+/// a whole number of lines, so nothing else can be placed next to it.
+pub fn padded(layout: Layout) -> Result<Layout, LayoutError> {
+    Ok(layout.align_to(CACHE_LINE)?.pad_to_align())
+}
+
+/// The padded layout of a `T`, for values that would otherwise be wrapped in
+/// a `CachePadded<T>`.
+pub fn padded_layout<T>() -> Layout {
+    padded(Layout::new::<T>()).expect("type too large to pad to a cache line")
+}
+
+/// Cache-line-aligned allocation from any allocator.
+pub trait CacheAlignedAlloc: GlobalAlloc {
+    /// Allocates `size` bytes on their own cache lines, or returns null. A
+    /// zero size gets a dangling, line-aligned pointer.
+    ///
+    /// Free the block with
+    /// [`dealloc_cache_aligned`](Self::dealloc_cache_aligned) and the same
+    /// `size`.
+    fn alloc_cache_aligned(&self, size: usize) -> *mut u8 {
+        match Layout::from_size_align(size, 1).and_then(padded) {
+            // a zero-size request would be undefined behavior for the allocator
+            Ok(layout) if layout.size() == 0 => zero_size::dangling(layout),
+            Ok(layout) => unsafe { self.alloc(layout) },
+            Err(_) => ptr::null_mut(),
+        }
+    }
+
+    /// Frees a block returned by
+    /// [`alloc_cache_aligned`](Self::alloc_cache_aligned).
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `alloc_cache_aligned` on this
+    /// allocator with the same `size`, and not freed since.
+    unsafe fn dealloc_cache_aligned(&self, ptr: *mut u8, size: usize) {
+        let layout =
+            unsafe { padded(Layout::from_size_align_unchecked(size, 1)).unwrap_unchecked() };
+        if zero_size::is_dangling(ptr, layout) {
+            return;
+        }
+        unsafe { self.dealloc(ptr, layout) }
+    }
+}
+
+impl<A: GlobalAlloc + ?Sized> CacheAlignedAlloc for A {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Align4096, BumpAllocator};
+
+    #[test]
+    fn test_blocks_do_not_share_lines() {
+        let allocator = BumpAllocator::<4096, Align4096>::new_aligned([0; 4096]);
+
+        let a = allocator.alloc_cache_aligned(1);
+        let b = allocator.alloc_cache_aligned(CACHE_LINE + 1);
+        let c = allocator.alloc_cache_aligned(8);
+        for ptr in [a, b, c] {
+            assert!(!ptr.is_null());
+            assert_eq!(ptr.addr() % CACHE_LINE, 0);
+        }
+        assert_eq!(b.addr() - a.addr(), CACHE_LINE);
+        assert_eq!(c.addr() - b.addr(), 2 * CACHE_LINE);
+
+        unsafe { allocator.dealloc_cache_aligned(a, 1) };
+        assert!(allocator.alloc_cache_aligned(usize::MAX).is_null());
+    }
+
+    #[test]
+    fn test_zero_size() {
+        let allocator = BumpAllocator::<256>::new([0; 256]);
+
+        let ptr = allocator.alloc_cache_aligned(0);
+        assert!(!ptr.is_null());
+        assert_eq!(ptr.addr() % CACHE_LINE, 0);
+        unsafe { allocator.dealloc_cache_aligned(ptr, 0) };
+        assert_eq!(allocator.largest_allocatable(1), 256);
+    }
+
+    #[test]
+    fn test_padded_layout() {
+        let layout = padded_layout::<[u8; 3]>();
+        assert_eq!((layout.size(), layout.align()), (CACHE_LINE, CACHE_LINE));
+
+        #[repr(align(4096))]
+        struct Page(#[allow(dead_code)] u8);
+        let layout = padded_layout::<Page>();
+        assert_eq!((layout.size(), layout.align()), (4096, 4096));
+    }
+}
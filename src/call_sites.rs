@@ -0,0 +1,203 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::panic::Location;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+use crate::Tagged;
+
+/// Wraps an allocator and accounts live bytes and blocks per call site, for
+/// allocations made through [`alloc_tracked`](Self::alloc_tracked) or the
+/// [`alloc_tracked!`](crate::alloc_tracked) macro.
+///
+/// Up to `SITES - 1` distinct call sites get their own entry. Allocations
+/// through plain `GlobalAlloc::alloc`, or from call sites beyond the table's
+/// capacity, are counted together as untracked.
+///
+/// The counters are those of [`Tagged`], one cache line per call site, so
+/// threads allocating from different sites don't contend.
+#[derive(Debug)]
+pub struct CallSites<A, const SITES: usize> {
+    tagged: Tagged<A, SITES>,
+    sites: [AtomicPtr<Location<'static>>; SITES],
+}
+
+// Tag 0 holds untracked allocations, call sites take the tags after it.
+const UNTRACKED: usize = 0;
+
+impl<A, const SITES: usize> CallSites<A, SITES> {
+    pub const fn new(inner: A) -> Self {
+        Self {
+            tagged: Tagged::new(inner),
+            sites: [const { AtomicPtr::new(ptr::null_mut()) }; SITES],
+        }
+    }
+
+    pub fn inner(&self) -> &A {
+        self.tagged.inner()
+    }
+
+    /// The tag of `location`, registering it if it is new.
+    fn tag_of(&self, location: &'static Location<'static>) -> usize {
+        for (tag, site) in self.sites.iter().enumerate().skip(1) {
+            let mut current = site.load(Ordering::Acquire);
+            if current.is_null() {
+                // claim the free slot, unless another thread just did
+                match site.compare_exchange(
+                    ptr::null_mut(),
+                    ptr::from_ref(location).cast_mut(),
+                    Ordering::AcqRel,
+                    Ordering::Acquire,
+                ) {
+                    Ok(_) => return tag,
+                    Err(claimed) => current = claimed,
+                }
+            }
+            if unsafe { *current } == *location {
+                return tag;
+            }
+        }
+        UNTRACKED
+    }
+
+    /// Live `(bytes, blocks)` per registered call site.
+    pub fn report(&self) -> impl Iterator<Item = (&'static Location<'static>, usize, usize)> + '_ {
+        self.sites
+            .iter()
+            .enumerate()
+            .skip(1)
+            .map_while(|(tag, site)| {
+                let location = unsafe { site.load(Ordering::Acquire).as_ref()? };
+                Some((
+                    location,
+                    self.tagged.bytes(tag),
+                    self.tagged.allocations(tag),
+                ))
+            })
+    }
+
+    /// Live `(bytes, blocks)` allocated without a tracked call site.
+    pub fn untracked(&self) -> (usize, usize) {
+        (
+            self.tagged.bytes(UNTRACKED),
+            self.tagged.allocations(UNTRACKED),
+        )
+    }
+}
+
+impl<A: GlobalAlloc, const SITES: usize> CallSites<A, SITES> {
+    /// Allocates `layout`, charged to the caller's location. Free the block
+    /// with `dealloc` as usual.
+    ///
+    /// # Safety
+    ///
+    /// Same contract as [`GlobalAlloc::alloc`].
+    #[track_caller]
+    pub unsafe fn alloc_tracked(&self, layout: Layout) -> *mut u8 {
+        let tag = self.tag_of(Location::caller());
+        unsafe { self.tagged.alloc_tagged(tag, layout) }
+    }
+}
+
+unsafe impl<A: GlobalAlloc, const SITES: usize> GlobalAlloc for CallSites<A, SITES> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        unsafe { self.tagged.alloc_tagged(UNTRACKED, layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.tagged.dealloc(ptr, layout) }
+    }
+
+    // keeps the block charged to the call site that allocated it
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        unsafe { self.tagged.realloc(ptr, layout, new_size) }
+    }
+}
+
+/// Allocates `layout` from a [`CallSites`] allocator, charged to the
+/// macro's call site. Must be used in an `unsafe` block, with the contract
+/// of [`GlobalAlloc::alloc`](core::alloc::GlobalAlloc::alloc).
+///
+/// ```
+/// use core::alloc::{GlobalAlloc, Layout};
+/// use simple_alloc::{BumpAllocator, CallSites, alloc_tracked};
+///
+/// let allocator = CallSites::<_, 8>::new(BumpAllocator::new([0; 1024]));
+/// let layout = Layout::new::<[u64; 4]>();
+/// let ptr = unsafe { alloc_tracked!(allocator, layout) };
+///
+/// let (site, bytes, blocks) = allocator.report().next().unwrap();
+/// assert_eq!(site.line(), line!() - 3);
+/// assert_eq!((bytes, blocks), (32, 1));
+/// unsafe { allocator.dealloc(ptr, layout) };
+/// ```
+#[macro_export]
+macro_rules! alloc_tracked {
+    ($allocator:expr, $layout:expr $(,)?) => {
+        $allocator.alloc_tracked($layout)
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::BumpAllocator;
+
+    test_suite! {
+        CallSites::<_, 4>::new(BumpAllocator::new([0; 65536])),
+        CallSites::<_, 4>::new(BumpAllocator::new([0; 256]))
+    }
+
+    #[test]
+    fn test_accounted_per_call_site() {
+        let allocator = CallSites::<_, 3>::new(BumpAllocator::new([0; 4096]));
+        let layout = Layout::from_size_align(16, 8).unwrap();
+
+        unsafe {
+            let mut ptrs = std::vec::Vec::new();
+            for _ in 0..3 {
+                ptrs.push(alloc_tracked!(allocator, layout));
+            }
+            let other = allocator.alloc_tracked(layout);
+            // the table is full, so a third site is untracked
+            let overflow = alloc_tracked!(allocator, layout);
+            let plain = allocator.alloc(layout);
+
+            let report = std::vec::Vec::from_iter(allocator.report());
+            assert_eq!(report.len(), 2);
+            assert_eq!((report[0].1, report[0].2), (48, 3));
+            assert_eq!((report[1].1, report[1].2), (16, 1));
+            assert_eq!(report[0].0.file(), file!());
+            assert_eq!(allocator.untracked(), (32, 2));
+
+            for ptr in ptrs {
+                allocator.dealloc(ptr, layout);
+            }
+            allocator.dealloc(other, layout);
+            allocator.dealloc(overflow, layout);
+            allocator.dealloc(plain, layout);
+        }
+        assert!(
+            allocator
+                .report()
+                .all(|(_, bytes, blocks)| bytes == 0 && blocks == 0)
+        );
+        assert_eq!(allocator.untracked(), (0, 0));
+    }
+
+    #[test]
+    fn test_realloc_keeps_call_site() {
+        let allocator = CallSites::<_, 3>::new(BumpAllocator::new([0; 4096]));
+        let layout = Layout::from_size_align(16, 8).unwrap();
+
+        unsafe {
+            let ptr = alloc_tracked!(allocator, layout);
+            let grown = allocator.realloc(ptr, layout, 64);
+            assert!(!grown.is_null());
+
+            let (_, bytes, blocks) = allocator.report().next().unwrap();
+            assert_eq!((bytes, blocks), (64, 1));
+            assert_eq!(allocator.untracked(), (0, 0));
+            allocator.dealloc(grown, Layout::from_size_align(64, 8).unwrap());
+        }
+    }
+}
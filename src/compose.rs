@@ -0,0 +1,163 @@
+/// Declares a static allocator built from a chain of wrappers, outermost
+/// first, ending with the heap at the bottom, so the nested type of a
+/// `#[global_allocator]` doesn't have to be spelled out twice by hand.
+///
+/// Layers, with their parameters:
+///
+/// - `tagged(TAGS)`: [`Tagged`](crate::Tagged)
+/// - `call_sites(SITES)`: [`CallSites`](crate::CallSites)
+/// - `reserve(SIZE)`: [`Reserve`](crate::Reserve)
+/// - `sampled(SAMPLES, rate)`: [`Sampled`](crate::Sampled)
+/// - `aged`: [`Aged`](crate::Aged)
+/// - `timed`: `Timed`, with the `latency-stats` feature
+/// - `traced(threshold)`: `Traced`, with the `tracing` feature
+/// - `forbid`: `AllocDisabler`, with the `forbid-alloc` feature
+///
+/// Heaps:
+///
+/// - `bump(HEAP_SIZE)`: [`BumpAllocator`](crate::BumpAllocator)
+/// - `heap`: an empty [`Heap`](crate::Heap), to be initialized at startup
+///
+/// ```
+/// use core::alloc::{GlobalAlloc, Layout};
+/// use simple_alloc::compose_allocator;
+///
+/// compose_allocator! {
+///     static ALLOCATOR = tagged(4) -> aged -> bump(1 << 16);
+/// }
+///
+/// // a Tagged<Aged<BumpAllocator<65536>>, 4>
+/// let layout = Layout::new::<u64>();
+/// let ptr = unsafe { ALLOCATOR.alloc_tagged(2, layout) };
+/// assert_eq!(ALLOCATOR.bytes(2), 8);
+/// assert_eq!(ALLOCATOR.inner().clock(), 1);
+/// unsafe { ALLOCATOR.dealloc(ptr, layout) };
+/// ```
+#[macro_export]
+macro_rules! compose_allocator {
+    ($(#[$attr:meta])* $vis:vis static $name:ident = $($layers:tt)+) => {
+        $crate::__compose_allocator_static! { [$(#[$attr])* $vis $name] [] $($layers)+ }
+    };
+}
+
+// Splits off the trailing `;`, which can't follow the `tt` repetition in the
+// public macro's pattern.
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __compose_allocator_static {
+    ([$($decl:tt)*] [$($layers:tt)*] ;) => {
+        $crate::__compose_allocator_declare! { $($decl)* ($($layers)*) }
+    };
+    ([$($decl:tt)*] [$($layers:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::__compose_allocator_static! { [$($decl)*] [$($layers)* $next] $($rest)* }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __compose_allocator_declare {
+    ($(#[$attr:meta])* $vis:vis $name:ident ($($layers:tt)*)) => {
+        $(#[$attr])*
+        $vis static $name: $crate::__compose_allocator_type!($($layers)*) =
+            $crate::__compose_allocator_new!($($layers)*);
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __compose_allocator_type {
+    (bump($size:expr)) => { $crate::BumpAllocator<{ $size }> };
+    (heap) => { $crate::Heap };
+    (tagged($tags:expr) -> $($inner:tt)+) => {
+        $crate::Tagged<$crate::__compose_allocator_type!($($inner)+), { $tags }>
+    };
+    (call_sites($sites:expr) -> $($inner:tt)+) => {
+        $crate::CallSites<$crate::__compose_allocator_type!($($inner)+), { $sites }>
+    };
+    (reserve($size:expr) -> $($inner:tt)+) => {
+        $crate::Reserve<$crate::__compose_allocator_type!($($inner)+), { $size }>
+    };
+    (sampled($samples:expr, $rate:expr) -> $($inner:tt)+) => {
+        $crate::Sampled<$crate::__compose_allocator_type!($($inner)+), { $samples }>
+    };
+    (aged -> $($inner:tt)+) => { $crate::Aged<$crate::__compose_allocator_type!($($inner)+)> };
+    (timed -> $($inner:tt)+) => { $crate::Timed<$crate::__compose_allocator_type!($($inner)+)> };
+    (traced($threshold:expr) -> $($inner:tt)+) => {
+        $crate::Traced<$crate::__compose_allocator_type!($($inner)+)>
+    };
+    (forbid -> $($inner:tt)+) => {
+        $crate::AllocDisabler<$crate::__compose_allocator_type!($($inner)+)>
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __compose_allocator_new {
+    (bump($size:expr)) => { $crate::BumpAllocator::new([0; $size]) };
+    (heap) => { $crate::Heap::empty() };
+    (tagged($tags:expr) -> $($inner:tt)+) => {
+        $crate::Tagged::new($crate::__compose_allocator_new!($($inner)+))
+    };
+    (call_sites($sites:expr) -> $($inner:tt)+) => {
+        $crate::CallSites::new($crate::__compose_allocator_new!($($inner)+))
+    };
+    (reserve($size:expr) -> $($inner:tt)+) => {
+        $crate::Reserve::new($crate::__compose_allocator_new!($($inner)+))
+    };
+    (sampled($samples:expr, $rate:expr) -> $($inner:tt)+) => {
+        $crate::Sampled::new($crate::__compose_allocator_new!($($inner)+), $rate)
+    };
+    (aged -> $($inner:tt)+) => { $crate::Aged::new($crate::__compose_allocator_new!($($inner)+)) };
+    (timed -> $($inner:tt)+) => { $crate::Timed::new($crate::__compose_allocator_new!($($inner)+)) };
+    (traced($threshold:expr) -> $($inner:tt)+) => {
+        $crate::Traced::new($crate::__compose_allocator_new!($($inner)+), $threshold)
+    };
+    (forbid -> $($inner:tt)+) => {
+        $crate::AllocDisabler::new($crate::__compose_allocator_new!($($inner)+))
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use core::alloc::{GlobalAlloc, Layout};
+
+    compose_allocator! {
+        static LAYERED = call_sites(4) -> reserve(256) -> sampled(8, 64) -> bump(4096);
+    }
+
+    compose_allocator! {
+        /// Initialized by the test.
+        static RUNTIME = tagged(2) -> heap;
+    }
+
+    #[test]
+    fn test_layers_nest_in_order() {
+        let _: &crate::CallSites<
+            crate::Reserve<crate::Sampled<crate::BumpAllocator<4096>, 8>, 256>,
+            4,
+        > = &LAYERED;
+        let layout = Layout::from_size_align(100, 8).unwrap();
+        unsafe {
+            let ptr = crate::alloc_tracked!(LAYERED, layout);
+            assert!(!ptr.is_null());
+            assert_eq!(LAYERED.report().count(), 1);
+            // the call site tag sits in front of the block
+            assert_eq!(LAYERED.inner().inner().allocated(), 108);
+            LAYERED.dealloc(ptr, layout);
+        }
+    }
+
+    #[test]
+    fn test_runtime_heap() {
+        extern crate std;
+        let region = std::boxed::Box::leak(std::vec![0u8; 1024].into_boxed_slice());
+        unsafe {
+            RUNTIME
+                .inner()
+                .init(region.as_mut_ptr().expose_provenance(), region.len());
+            let ptr = RUNTIME.alloc_tagged(1, Layout::new::<u32>());
+            assert!(!ptr.is_null());
+        }
+        assert_eq!(RUNTIME.report(), [0, 4]);
+    }
+}
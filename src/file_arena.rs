@@ -0,0 +1,351 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+use core::sync::atomic::{AtomicU64, Ordering};
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::os::fd::AsRawFd;
+use std::path::Path;
+
+use crate::zero_size;
+
+/// A bump arena backed by a memory-mapped file.
+///
+/// Everything allocated from the arena lives in the file, so data structures
+/// built in it can be persisted with [`flush`](Self::flush) and picked up
+/// again by [`open`](Self::open), possibly from another run of the program.
+/// The mapping address changes between runs, so structures should link to
+/// each other by offset (see [`offset_of`](Self::offset_of) and
+/// [`ptr_at`](Self::ptr_at)) and record their entry point with
+/// [`set_root`](Self::set_root).
+///
+/// Like [`BumpAllocator`](crate::BumpAllocator), `dealloc` never frees.
+#[derive(Debug)]
+pub struct FileArena {
+    base: *mut u8,
+    size: usize,
+}
+
+unsafe impl Send for FileArena {}
+unsafe impl Sync for FileArena {}
+
+const MAGIC: u64 = u64::from_le_bytes(*b"filearna");
+const VERSION: u32 = 1;
+
+/// Stored at the start of the file. `open` refuses files whose header was
+/// written by an incompatible build.
+#[repr(C)]
+struct ArenaHeader {
+    magic: u64,
+    version: u32,
+    pointer_width: u16,
+    little_endian: u16,
+    size: u64,
+    next_free: AtomicU64,
+    root: AtomicU64,
+}
+
+const HEAP_START: usize = size_of::<ArenaHeader>().next_multiple_of(16);
+
+impl FileArena {
+    /// Creates (or truncates) the file at `path` and sets it up as an empty
+    /// arena of `size` bytes, header included.
+    pub fn create(path: impl AsRef<Path>, size: usize) -> io::Result<Self> {
+        if size < HEAP_START {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "file arena too small",
+            ));
+        }
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(path)?;
+        file.set_len(size as u64)?;
+        let arena = Self::map(&file, size)?;
+        unsafe {
+            arena.header().write(ArenaHeader {
+                magic: MAGIC,
+                version: VERSION,
+                pointer_width: usize::BITS as u16,
+                little_endian: cfg!(target_endian = "little") as u16,
+                size: size as u64,
+                next_free: AtomicU64::new(HEAP_START as u64),
+                root: AtomicU64::new(0),
+            });
+        }
+        arena.flush()?;
+        Ok(arena)
+    }
+
+    /// Maps an arena previously written by [`create`](Self::create).
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = OpenOptions::new().read(true).write(true).open(path)?;
+        let size = file.metadata()?.len() as usize;
+        let invalid = |msg| Err(io::Error::new(io::ErrorKind::InvalidData, msg));
+        if size < HEAP_START {
+            return invalid("not a file arena");
+        }
+        let arena = Self::map(&file, size)?;
+        let header = unsafe { &*arena.header() };
+        if header.magic != MAGIC {
+            return invalid("not a file arena");
+        }
+        if header.version != VERSION {
+            return invalid("unsupported file arena version");
+        }
+        if header.pointer_width != usize::BITS as u16
+            || header.little_endian != cfg!(target_endian = "little") as u16
+        {
+            return invalid("file arena was written by an incompatible target");
+        }
+        if header.size != size as u64 {
+            return invalid("file arena size does not match its header");
+        }
+        // blocks must not overlap the header or run past the end
+        let next_free = header.next_free.load(Ordering::Relaxed);
+        if !(HEAP_START as u64..=header.size).contains(&next_free) {
+            return invalid("file arena allocation state is corrupted");
+        }
+        if header.root.load(Ordering::Relaxed) >= header.size {
+            return invalid("file arena root is outside the arena");
+        }
+        Ok(arena)
+    }
+
+    fn map(file: &File, size: usize) -> io::Result<Self> {
+        let base = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if base == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            base: base.cast(),
+            size,
+        })
+    }
+
+    fn header(&self) -> *mut ArenaHeader {
+        self.base.cast()
+    }
+
+    /// Writes every modified page of the arena back to the file.
+    pub fn flush(&self) -> io::Result<()> {
+        self.msync(self.base, self.size)
+    }
+
+    /// Writes back the pages covering `len` bytes at `ptr`, plus the header
+    /// so the arena's allocation state is persisted along with them.
+    pub fn flush_range(&self, ptr: *const u8, len: usize) -> io::Result<()> {
+        let start = self.offset_of(ptr);
+        assert!(start + len <= self.size, "range is outside the arena");
+        let page = page_size();
+        let aligned_start = start / page * page;
+        let aligned_ptr = unsafe { self.base.add(aligned_start) };
+        self.msync(aligned_ptr, start + len - aligned_start)?;
+        self.msync(self.base, HEAP_START)
+    }
+
+    fn msync(&self, ptr: *mut u8, len: usize) -> io::Result<()> {
+        if unsafe { libc::msync(ptr.cast(), len, libc::MS_SYNC) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    /// Total size of the arena, header included.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Offset of `ptr` from the start of the arena, stable across reopens.
+    pub fn offset_of(&self, ptr: *const u8) -> usize {
+        debug_assert!((self.base.addr()..self.base.addr() + self.size).contains(&ptr.addr()));
+        ptr.addr() - self.base.addr()
+    }
+
+    /// Pointer to `offset` in the current mapping, or `None` if the offset is
+    /// outside the arena, e.g. because it was read from a corrupted file.
+    pub fn ptr_at(&self, offset: usize) -> Option<*mut u8> {
+        if offset >= self.size {
+            return None;
+        }
+        Some(unsafe { self.base.add(offset) })
+    }
+
+    /// Records the offset of the arena's entry point, e.g. the head of a
+    /// persisted data structure. Zero means no root.
+    pub fn set_root(&self, offset: usize) {
+        unsafe {
+            (*self.header())
+                .root
+                .store(offset as u64, Ordering::Release)
+        }
+    }
+
+    pub fn root(&self) -> usize {
+        unsafe { (*self.header()).root.load(Ordering::Acquire) as usize }
+    }
+}
+
+fn page_size() -> usize {
+    unsafe { libc::sysconf(libc::_SC_PAGESIZE) as usize }
+}
+
+impl Drop for FileArena {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.base.cast(), self.size) };
+    }
+}
+
+unsafe impl GlobalAlloc for FileArena {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == 0 {
+            return zero_size::dangling(layout);
+        }
+        let base = self.base.addr();
+        let next_free = unsafe { &(*self.header()).next_free };
+        let mut block_start = 0;
+        let result = next_free.fetch_update(Ordering::AcqRel, Ordering::Acquire, |next| {
+            // align the address rather than the offset, the base is only page
+            // aligned
+            let start = (base + next as usize).next_multiple_of(layout.align()) - base;
+            let end = start.checked_add(layout.size())?;
+            if end > self.size {
+                return None;
+            }
+            block_start = start;
+            Some(end as u64)
+        });
+        if result.is_err() {
+            return ptr::null_mut();
+        }
+        // the block ends within the arena
+        unsafe { self.base.add(block_start) }
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn arena_path() -> PathBuf {
+        use core::sync::atomic::AtomicUsize;
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let id = NEXT.fetch_add(1, Ordering::Relaxed);
+        std::env::temp_dir().join(std::format!(
+            "simple-alloc-arena-{}-{id}",
+            std::process::id()
+        ))
+    }
+
+    // The file is removed right away; the mapping keeps its contents alive.
+    fn temporary(size: usize) -> FileArena {
+        let path = arena_path();
+        let arena = FileArena::create(&path, size).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        arena
+    }
+
+    test_suite! {
+        temporary(65536),
+        temporary(256)
+    }
+
+    #[test]
+    fn test_persist_and_reopen() {
+        let path = arena_path();
+        let message = b"persisted through the file";
+
+        {
+            let arena = FileArena::create(&path, 4096).unwrap();
+            let layout = Layout::for_value(message);
+            unsafe {
+                let ptr = arena.alloc(layout);
+                assert!(!ptr.is_null());
+                ptr.copy_from_nonoverlapping(message.as_ptr(), message.len());
+                arena.flush_range(ptr, message.len()).unwrap();
+                arena.set_root(arena.offset_of(ptr));
+            }
+            arena.flush().unwrap();
+        }
+
+        let arena = FileArena::open(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let root = arena.ptr_at(arena.root()).unwrap();
+        let persisted = unsafe { std::slice::from_raw_parts(root, message.len()) };
+        assert_eq!(persisted, message);
+
+        // allocation continues after the persisted data
+        unsafe {
+            let layout = Layout::from_size_align(16, 1).unwrap();
+            let ptr = arena.alloc(layout);
+            assert!(arena.offset_of(ptr) >= arena.root() + message.len());
+        }
+    }
+
+    #[test]
+    fn test_open_rejects_incompatible_header() {
+        let path = arena_path();
+        drop(FileArena::create(&path, 4096).unwrap());
+
+        // bump the version as a future format would
+        {
+            let arena = FileArena::open(&path).unwrap();
+            unsafe { (*arena.header()).version = VERSION + 1 };
+            arena.flush().unwrap();
+        }
+        let err = FileArena::open(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        std::fs::write(&path, [0u8; 4096]).unwrap();
+        let err = FileArena::open(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_open_rejects_corrupted_offsets() {
+        let path = arena_path();
+        let corrupt = |f: fn(&ArenaHeader)| {
+            drop(FileArena::create(&path, 4096).unwrap());
+            let arena = FileArena::open(&path).unwrap();
+            f(unsafe { &*arena.header() });
+            arena.flush().unwrap();
+        };
+
+        // blocks would overlap the header
+        corrupt(|header| header.next_free.store(8, Ordering::Relaxed));
+        let err = FileArena::open(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        corrupt(|header| header.next_free.store(4097, Ordering::Relaxed));
+        let err = FileArena::open(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        corrupt(|header| header.root.store(4096, Ordering::Relaxed));
+        let err = FileArena::open(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        // a full arena is fine
+        corrupt(|header| header.next_free.store(4096, Ordering::Relaxed));
+        let arena = FileArena::open(&path).unwrap();
+        assert!(arena.ptr_at(4095).is_some());
+        assert!(arena.ptr_at(4096).is_none());
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
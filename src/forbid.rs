@@ -0,0 +1,136 @@
+use core::alloc::{GlobalAlloc, Layout};
+
+/// Wraps an allocator so that using it inside [`forbid_alloc`] aborts the
+/// process, after printing which operation was attempted.
+///
+/// Install it as the global allocator in test builds to prove that
+/// real-time code, such as an audio callback or an interrupt handler, never
+/// reaches the allocator. It aborts rather than panics because unwinding out
+/// of a global allocator is undefined behavior, so a violation can't be
+/// caught with `#[should_panic]`; run the offending code in a child process
+/// to test for it.
+#[derive(Debug)]
+pub struct AllocDisabler<A> {
+    inner: A,
+}
+
+impl<A> AllocDisabler<A> {
+    pub const fn new(inner: A) -> Self {
+        Self { inner }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+}
+
+#[cfg(feature = "std")]
+std::thread_local! {
+    static FORBIDDEN: core::cell::Cell<usize> = const { core::cell::Cell::new(0) };
+}
+
+/// Without std there is a single scope for the whole program, which suits
+/// single-core targets.
+#[cfg(not(feature = "std"))]
+static FORBIDDEN: Forbidden = Forbidden(core::sync::atomic::AtomicUsize::new(0));
+
+#[cfg(not(feature = "std"))]
+struct Forbidden(core::sync::atomic::AtomicUsize);
+
+#[cfg(not(feature = "std"))]
+impl Forbidden {
+    fn get(&self) -> usize {
+        self.0.load(core::sync::atomic::Ordering::Relaxed)
+    }
+
+    fn set(&self, depth: usize) {
+        self.0.store(depth, core::sync::atomic::Ordering::Relaxed)
+    }
+}
+
+/// Runs `f`, aborting the process if it allocates or frees through an
+/// [`AllocDisabler`]. With `std`, the check only covers the calling thread.
+pub fn forbid_alloc<R>(f: impl FnOnce() -> R) -> R {
+    struct Restore(usize);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            FORBIDDEN.set(self.0);
+        }
+    }
+
+    let depth = FORBIDDEN.get();
+    let _restore = Restore(depth);
+    FORBIDDEN.set(depth + 1);
+    f()
+}
+
+fn check(operation: Operation, layout: Layout) {
+    if FORBIDDEN.get() > 0 {
+        // reporting may allocate
+        FORBIDDEN.set(0);
+        report(operation, layout.size(), layout.align());
+    }
+}
+
+/// Prints the violation through the panic hook, then aborts: a panic can't
+/// unwind out of an `extern "C"` function. Takes only FFI-safe arguments.
+#[cold]
+extern "C" fn report(operation: Operation, size: usize, align: usize) -> ! {
+    let operation = match operation {
+        Operation::Alloc => "allocation",
+        Operation::Dealloc => "deallocation",
+        Operation::Realloc => "reallocation",
+    };
+    panic!("{operation} of {size} bytes (align {align}) inside forbid_alloc");
+}
+
+#[repr(u8)]
+#[derive(Clone, Copy)]
+enum Operation {
+    Alloc,
+    Dealloc,
+    Realloc,
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for AllocDisabler<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        check(Operation::Alloc, layout);
+        unsafe { self.inner.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        check(Operation::Dealloc, layout);
+        unsafe { self.inner.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        check(Operation::Realloc, layout);
+        unsafe { self.inner.realloc(ptr, layout, new_size) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::BumpAllocator;
+
+    test_suite! {
+        AllocDisabler::new(BumpAllocator::new([0; 65536])),
+        AllocDisabler::new(BumpAllocator::new([0; 256]))
+    }
+
+    #[test]
+    fn test_scopes_nest_and_restore() {
+        let allocator = AllocDisabler::new(BumpAllocator::new([0; 256]));
+        let layout = Layout::from_size_align(48, 8).unwrap();
+
+        let ptr = unsafe { allocator.alloc(layout) };
+        assert!(!ptr.is_null());
+        let value = forbid_alloc(|| forbid_alloc(|| unsafe { ptr.read() }));
+        assert_eq!(value, 0);
+
+        // outside the scope again
+        assert!(!unsafe { allocator.alloc(layout) }.is_null());
+    }
+}
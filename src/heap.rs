@@ -0,0 +1,310 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::ptr;
+use core::sync::atomic::{AtomicBool, AtomicPtr, Ordering};
+
+use crate::region::{ConstAlign, HeapRegion, SupportedAlign};
+use crate::zero_size;
+
+/// A bump allocator over a memory region given at runtime.
+///
+/// Unlike [`BumpAllocator`](crate::BumpAllocator), which owns its heap
+/// array, a `Heap` can be declared as a static before the region is known,
+/// e.g. when it comes from a device tree or a bootloader handoff:
+///
+/// ```no_run
+/// use simple_alloc::Heap;
+///
+/// #[global_allocator]
+/// static HEAP: Heap = Heap::empty();
+///
+/// fn main() {
+///     let (start, size) = (0x2000_0000, 64 * 1024); // discovered at boot
+///     unsafe { HEAP.init(start, size) };
+/// }
+/// ```
+///
+/// Allocations fail until [`init`](Self::init) is called. Like
+/// `BumpAllocator`, `dealloc` never frees.
+#[derive(Debug)]
+pub struct Heap {
+    // set by the first init, before the bounds are written
+    claimed: AtomicBool,
+    start: AtomicPtr<u8>,
+    next_free: AtomicPtr<u8>,
+    end: AtomicPtr<u8>,
+}
+
+impl Heap {
+    pub const fn empty() -> Self {
+        Self {
+            claimed: AtomicBool::new(false),
+            start: AtomicPtr::new(ptr::null_mut()),
+            next_free: AtomicPtr::new(ptr::null_mut()),
+            end: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// Hands the region of `size` bytes at address `start` to the heap.
+    ///
+    /// # Safety
+    ///
+    /// The region must be valid for reads and writes, unused by anything
+    /// else for as long as the heap is, and its address exposed (see
+    /// [`ptr::with_exposed_provenance_mut`]). `init` must be called once,
+    /// before any allocation that should succeed; a second call panics.
+    pub unsafe fn init(&self, start: usize, size: usize) {
+        let start = ptr::with_exposed_provenance_mut::<u8>(start);
+        assert!(!start.is_null(), "heap region must not start at null");
+        self.claim();
+        unsafe { self.set_bounds(start, size) };
+    }
+
+    /// Hands a static [`HeapRegion`] to the heap, with the same panics as
+    /// [`init`](Self::init). Panics as well if the region was already claimed.
+    /// Either way, nothing is claimed if it panics.
+    pub fn init_region<const N: usize, const ALIGN: usize>(
+        &self,
+        region: &'static HeapRegion<N, ALIGN>,
+    ) where
+        ConstAlign<ALIGN>: SupportedAlign,
+    {
+        self.claim();
+        let Some(bytes) = region.claim() else {
+            self.claimed.store(false, Ordering::Relaxed);
+            panic!("heap region already claimed");
+        };
+        // the region is ours alone now, and lives as long as the heap
+        unsafe { self.set_bounds(bytes, N) };
+    }
+
+    /// Claims the heap before its bounds are written, so a second init can't
+    /// touch the bounds of one in use.
+    fn claim(&self) {
+        let claimed = self.claimed.swap(true, Ordering::Relaxed);
+        assert!(!claimed, "heap initialized twice");
+    }
+
+    unsafe fn set_bounds(&self, start: *mut u8, size: usize) {
+        let end = unsafe { start.add(size) };
+        self.start.store(start, Ordering::Relaxed);
+        self.end.store(end, Ordering::Relaxed);
+        // publishing the start makes the heap usable, so do it last
+        self.next_free.store(start, Ordering::Release);
+    }
+}
+
+impl Heap {
+    /// How full the heap is, in thousandths. An uninitialized heap is empty.
+    pub fn occupancy_permille(&self) -> u32 {
+        let (start, next_free, end) = self.bounds();
+        let size = end - start;
+        if size == 0 {
+            return 0;
+        }
+        ((next_free - start) as u64 * 1000 / size as u64) as u32
+    }
+
+    /// Size of the largest block with alignment `align` that an allocation
+    /// would succeed for right now. Other threads may allocate in the
+    /// meantime.
+    ///
+    /// # Panics
+    ///
+    /// If `align` is not a power of two.
+    pub fn largest_allocatable(&self, align: usize) -> usize {
+        assert!(align.is_power_of_two(), "alignment must be a power of two");
+        let (_, next_free, end) = self.bounds();
+        end.saturating_sub(next_free.next_multiple_of(align))
+    }
+
+    /// Addresses of the start, first free byte and end of the heap, all zero
+    /// before `init`.
+    fn bounds(&self) -> (usize, usize, usize) {
+        let next_free = self.next_free.load(Ordering::Acquire);
+        if next_free.is_null() {
+            return (0, 0, 0);
+        }
+        (
+            self.start.load(Ordering::Relaxed).addr(),
+            next_free.addr(),
+            self.end.load(Ordering::Relaxed).addr(),
+        )
+    }
+
+    /// Moves the heap to the region at address `new_start`, which must be as
+    /// large as the current one. The allocated part of the heap is copied
+    /// over, the regions may overlap, and allocation continues after it in
+    /// the new region.
+    ///
+    /// Blocks keep their offset from the start of the heap, so anything
+    /// pointing into the heap has to be rebased by the caller, e.g. because
+    /// all accesses go through offsets or handles.
+    ///
+    /// # Safety
+    ///
+    /// The heap must be initialized, and not used concurrently. The new
+    /// region must meet the requirements of [`init`](Self::init). No pointer
+    /// into the old region may be used afterwards.
+    pub unsafe fn relocate(&self, new_start: usize) {
+        let old_start = self.start.load(Ordering::Relaxed);
+        assert!(!old_start.is_null(), "relocating an uninitialized heap");
+        let new_start = ptr::with_exposed_provenance_mut::<u8>(new_start);
+        let used = self.next_free.load(Ordering::Acquire).addr() - old_start.addr();
+        let size = self.end.load(Ordering::Relaxed).addr() - old_start.addr();
+        unsafe {
+            ptr::copy(old_start, new_start, used);
+            self.start.store(new_start, Ordering::Relaxed);
+            self.end.store(new_start.add(size), Ordering::Relaxed);
+            self.next_free.store(new_start.add(used), Ordering::Release);
+        }
+    }
+}
+
+impl Default for Heap {
+    fn default() -> Self {
+        Self::empty()
+    }
+}
+
+unsafe impl GlobalAlloc for Heap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == 0 {
+            return zero_size::dangling(layout);
+        }
+        let mut block_start = ptr::null_mut();
+        // The loads need Acquire to see the bounds written by init, which
+        // publishes them with a Release store of next_free. The update
+        // publishes nothing, as in BumpAllocator.
+        let result =
+            self.next_free
+                .fetch_update(Ordering::Relaxed, Ordering::Acquire, |next_free| {
+                    if next_free.is_null() {
+                        // not initialized yet
+                        return None;
+                    }
+                    let end = self.end.load(Ordering::Relaxed);
+                    let start = next_free.map_addr(|addr| addr.next_multiple_of(layout.align()));
+                    let available = end.addr().checked_sub(start.addr())?;
+                    if layout.size() > available {
+                        return None;
+                    }
+                    block_start = start;
+                    Some(unsafe { start.add(layout.size()) })
+                });
+        if result.is_err() {
+            return ptr::null_mut();
+        }
+        block_start
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::boxed::Box;
+
+    fn initialized(size: usize) -> Heap {
+        let heap = Heap::empty();
+        let region = Box::leak(std::vec![0u8; size].into_boxed_slice());
+        unsafe { heap.init(region.as_mut_ptr().expose_provenance(), size) };
+        heap
+    }
+
+    test_suite! {
+        initialized(65536),
+        initialized(256)
+    }
+
+    #[test]
+    fn test_relocate() {
+        let heap = initialized(1024);
+        let mut new_region = [0u8; 1024];
+        let layout = Layout::from_size_align(64, 8).unwrap();
+
+        unsafe {
+            let old = heap.alloc(layout);
+            old.write_bytes(0xAB, 64);
+            let offset = old.addr() - heap.start.load(Ordering::Relaxed).addr();
+
+            heap.relocate(new_region.as_mut_ptr().expose_provenance());
+
+            let moved = &new_region[offset..offset + 64];
+            assert!(moved.iter().all(|&b| b == 0xAB));
+            let next = heap.alloc(layout);
+            let new_range = new_region.as_ptr_range();
+            assert!(new_range.contains(&next.cast_const()));
+            assert!(next.addr() >= new_range.start.addr() + offset + 64);
+        }
+    }
+
+    #[test]
+    fn test_huge_page_alignment() {
+        // wherever the region lands, 4 MiB holds a 2 MiB boundary with 1 MiB
+        // after it
+        let heap = initialized(4 << 20);
+        let layout = Layout::from_size_align(1 << 20, 2 << 20).unwrap();
+
+        unsafe {
+            let small = heap.alloc(Layout::new::<u8>());
+            let huge = heap.alloc(layout);
+            assert!(!huge.is_null());
+            assert_eq!(huge.addr() % (2 << 20), 0);
+            assert!(huge.addr() > small.addr());
+            huge.write_bytes(0xAB, 1 << 20);
+        }
+    }
+
+    #[test]
+    fn test_uninitialized_heap_fails() {
+        let heap = Heap::empty();
+        unsafe {
+            assert!(heap.alloc(Layout::from_size_align(1, 1).unwrap()).is_null());
+        }
+        assert_eq!(heap.occupancy_permille(), 0);
+        assert_eq!(heap.largest_allocatable(1), 0);
+    }
+
+    #[test]
+    fn test_occupancy_and_largest_allocatable() {
+        let heap = initialized(1000);
+        assert_eq!(heap.largest_allocatable(1), 1000);
+
+        unsafe { heap.alloc(Layout::from_size_align(250, 1).unwrap()) };
+        assert_eq!(heap.occupancy_permille(), 250);
+        assert_eq!(heap.largest_allocatable(1), 750);
+
+        let largest = heap.largest_allocatable(64);
+        assert!(largest <= 750 && largest > 750 - 64);
+        let layout = Layout::from_size_align(largest, 64).unwrap();
+        assert!(!unsafe { heap.alloc(layout) }.is_null());
+        assert_eq!(heap.occupancy_permille(), 1000);
+    }
+
+    #[test]
+    #[should_panic(expected = "alignment must be a power of two")]
+    fn test_largest_allocatable_rejects_non_power_of_two() {
+        initialized(256).largest_allocatable(24);
+    }
+
+    #[test]
+    fn test_init_twice_panics() {
+        let heap = initialized(256);
+        let layout = Layout::from_size_align(16, 8).unwrap();
+        let first = unsafe { heap.alloc(layout) };
+        let mut region = [0u8; 1024];
+
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| unsafe {
+            heap.init(region.as_mut_ptr().expose_provenance(), region.len())
+        }));
+        let message = *result.unwrap_err().downcast::<&str>().unwrap();
+        assert_eq!(message, "heap initialized twice");
+
+        // the heap still serves its original region
+        assert_eq!(heap.largest_allocatable(1), 240);
+        let next = unsafe { heap.alloc(layout) };
+        assert_eq!(next.addr(), first.addr() + 16);
+        assert!(!region.as_ptr_range().contains(&next.cast_const()));
+    }
+}
@@ -0,0 +1,230 @@
+//! Intrusive free lists, which keep their links inside the free blocks
+//! themselves, so tracking a free block costs no memory.
+//!
+//! Links are stored as pointers, never as bare addresses, so a block popped
+//! from a list comes back with the provenance it was pushed with.
+//!
+//! [`FreeList`] is a plain LIFO list for single-threaded allocators or ones
+//! behind a lock. [`AtomicStack`] can be pushed to from any thread, and is
+//! drained all at once with [`take_all`](AtomicStack::take_all): popping
+//! single blocks off a lock-free stack is prone to ABA, taking the whole
+//! stack is not.
+
+use core::fmt;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, Ordering};
+
+/// The link written to the start of each free block. A block must be at
+/// least this large and aligned to hold it.
+#[repr(C)]
+struct Node {
+    next: *mut Node,
+}
+
+/// A LIFO list of free blocks, linked through the blocks.
+///
+/// ```
+/// use simple_alloc::intrusive::FreeList;
+///
+/// let mut blocks = [[0usize; 4]; 2];
+/// let mut list = FreeList::new();
+/// for block in &mut blocks {
+///     unsafe { list.push(block.as_mut_ptr().cast()) };
+/// }
+/// assert_eq!(list.pop(), blocks[1].as_mut_ptr().cast());
+/// ```
+pub struct FreeList {
+    head: *mut Node,
+}
+
+impl FreeList {
+    pub const fn new() -> Self {
+        Self {
+            head: ptr::null_mut(),
+        }
+    }
+
+    pub const fn is_empty(&self) -> bool {
+        self.head.is_null()
+    }
+
+    /// Adds `block` to the front of the list, overwriting its first word.
+    ///
+    /// # Safety
+    ///
+    /// `block` must be valid for writes of a pointer and aligned for one. It
+    /// must not be in a list already, and must not be used again until it is
+    /// popped.
+    pub unsafe fn push(&mut self, block: *mut u8) {
+        let node = block.cast::<Node>();
+        unsafe { node.write(Node { next: self.head }) };
+        self.head = node;
+    }
+
+    /// Removes the most recently pushed block, or returns null if the list is
+    /// empty.
+    pub fn pop(&mut self) -> *mut u8 {
+        let node = self.head;
+        if !node.is_null() {
+            self.head = unsafe { (*node).next };
+        }
+        node.cast()
+    }
+
+    /// The blocks in the list, most recently pushed first.
+    pub fn iter(&self) -> Iter<'_> {
+        Iter {
+            node: self.head,
+            _list: self,
+        }
+    }
+
+    /// Counts the blocks, walking the whole list.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+}
+
+impl Default for FreeList {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for FreeList {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+/// An iterator over the blocks of a [`FreeList`].
+#[derive(Debug)]
+pub struct Iter<'a> {
+    node: *mut Node,
+    _list: &'a FreeList,
+}
+
+impl Iterator for Iter<'_> {
+    type Item = *mut u8;
+
+    fn next(&mut self) -> Option<*mut u8> {
+        let node = self.node;
+        if node.is_null() {
+            return None;
+        }
+        self.node = unsafe { (*node).next };
+        Some(node.cast())
+    }
+}
+
+/// A stack of free blocks that any thread can push to without locking,
+/// e.g. for blocks freed by a thread other than the one owning the heap.
+#[derive(Debug)]
+pub struct AtomicStack {
+    head: AtomicPtr<Node>,
+}
+
+impl AtomicStack {
+    pub const fn new() -> Self {
+        Self {
+            head: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.head.load(Ordering::Relaxed).is_null()
+    }
+
+    /// Adds `block` to the stack, overwriting its first word.
+    ///
+    /// # Safety
+    ///
+    /// As for [`FreeList::push`].
+    pub unsafe fn push(&self, block: *mut u8) {
+        let node = block.cast::<Node>();
+        let mut head = self.head.load(Ordering::Relaxed);
+        loop {
+            unsafe { node.write(Node { next: head }) };
+            // Release publishes the link to whoever takes the stack
+            match self
+                .head
+                .compare_exchange_weak(head, node, Ordering::Release, Ordering::Relaxed)
+            {
+                Ok(_) => return,
+                Err(current) => head = current,
+            }
+        }
+    }
+
+    /// Empties the stack, returning its blocks as a list, most recently
+    /// pushed first.
+    pub fn take_all(&self) -> FreeList {
+        FreeList {
+            head: self.head.swap(ptr::null_mut(), Ordering::Acquire),
+        }
+    }
+}
+
+impl Default for AtomicStack {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    extern crate std;
+    use std::vec::Vec;
+
+    #[test]
+    fn test_free_list_is_lifo() {
+        let mut blocks = [[0usize; 2]; 3];
+        let ptrs: Vec<*mut u8> = blocks.iter_mut().map(|b| b.as_mut_ptr().cast()).collect();
+        let mut list = FreeList::new();
+        assert!(list.is_empty());
+
+        for &ptr in &ptrs {
+            unsafe { list.push(ptr) };
+        }
+        assert_eq!(list.len(), 3);
+        assert!(list.iter().eq(ptrs.iter().rev().copied()));
+
+        for &ptr in ptrs.iter().rev() {
+            let block = list.pop();
+            assert_eq!(block, ptr);
+            // the popped pointer must still be usable for the whole block
+            unsafe { block.cast::<[usize; 2]>().write([1, 2]) };
+        }
+        assert!(list.pop().is_null());
+        assert!(list.is_empty());
+        assert_eq!(blocks, [[1, 2]; 3]);
+    }
+
+    #[test]
+    fn test_atomic_stack_collects_pushes_from_all_threads() {
+        const THREADS: usize = 4;
+        const PER_THREAD: usize = 100;
+
+        let mut blocks = std::vec![0usize; THREADS * PER_THREAD];
+        let expected: Vec<usize> = blocks.iter().map(|b| ptr::from_ref(b).addr()).collect();
+        let stack = AtomicStack::new();
+        std::thread::scope(|s| {
+            for chunk in blocks.chunks_mut(PER_THREAD) {
+                let stack = &stack;
+                s.spawn(move || {
+                    for block in chunk {
+                        unsafe { stack.push(ptr::from_mut(block).cast()) };
+                    }
+                });
+            }
+        });
+
+        let mut list = stack.take_all();
+        assert!(stack.is_empty());
+        let mut addrs: Vec<usize> = list.iter().map(|block| block.addr()).collect();
+        addrs.sort_unstable();
+        assert_eq!(addrs, expected);
+        while !list.pop().is_null() {}
+    }
+}
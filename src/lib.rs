@@ -1,5 +1,83 @@
 #![no_std]
-#[cfg(test)]
+#[cfg(feature = "std")]
+extern crate std;
 #[macro_use]
-mod test_utils;
+pub mod test_utils;
+mod aged;
+mod any;
+mod arena;
+#[cfg(feature = "asan")]
+mod asan;
 mod bump_allocator;
+mod bump_vec;
+pub mod cache_line;
+mod call_sites;
+mod compose;
+#[cfg(all(feature = "std", unix))]
+mod file_arena;
+#[cfg(feature = "forbid-alloc")]
+mod forbid;
+mod heap;
+pub mod intrusive;
+mod locked;
+mod monotonic;
+#[cfg(feature = "msan")]
+mod msan;
+#[cfg(all(feature = "std", unix))]
+mod offset_ptr;
+mod oom;
+mod passthrough;
+mod pool;
+mod region;
+mod reserve;
+mod resource;
+mod sampled;
+#[cfg(all(feature = "std", unix))]
+mod shared_mem;
+mod sync;
+mod tagged;
+#[cfg(feature = "latency-stats")]
+mod timed;
+#[cfg(feature = "tracing")]
+mod traced;
+#[cfg(feature = "valgrind")]
+mod valgrind;
+#[cfg(any(all(feature = "wasm", target_arch = "wasm32"), test))]
+mod wasm;
+pub mod zero_size;
+
+pub use aged::{Aged, LiveAllocation};
+pub use any::AnyAllocator;
+pub use arena::Arena;
+pub use bump_allocator::{
+    Align2MiB, Align16, Align64, Align4096, BumpAllocator, HeapSnapshot, Reservation,
+};
+pub use bump_vec::BumpVec;
+pub use call_sites::CallSites;
+#[cfg(all(feature = "std", unix))]
+pub use file_arena::FileArena;
+#[cfg(feature = "forbid-alloc")]
+pub use forbid::{AllocDisabler, forbid_alloc};
+pub use heap::Heap;
+pub use locked::{Locked, UnsyncAlloc};
+pub use monotonic::MonotonicResource;
+#[cfg(all(feature = "std", unix))]
+pub use offset_ptr::{OffsetPtr, ShmBox};
+#[cfg(feature = "debug-oom")]
+pub use oom::OomInfo;
+pub use oom::{AllocError, OomPolicy};
+pub use passthrough::Passthrough;
+pub use pool::UnsyncPoolResource;
+pub use region::{ConstAlign, HeapRegion, RegionStorage, SupportedAlign};
+pub use reserve::Reserve;
+pub use resource::MemoryResource;
+pub use sampled::{Sample, Sampled};
+#[cfg(all(feature = "std", unix))]
+pub use shared_mem::SharedMemAllocator;
+pub use tagged::{Account, Tagged};
+#[cfg(feature = "latency-stats")]
+pub use timed::{LatencyHistogram, Operation, Timed};
+#[cfg(feature = "tracing")]
+pub use traced::Traced;
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+pub use wasm::WasmAllocator;
@@ -0,0 +1,271 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// An allocator whose state needs exclusive access, made usable as a
+/// `GlobalAlloc` by wrapping it in [`Locked`].
+pub trait UnsyncAlloc {
+    /// Same contract as [`GlobalAlloc::alloc`].
+    fn alloc(&mut self, layout: Layout) -> *mut u8;
+
+    /// Same contract as [`GlobalAlloc::dealloc`].
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `alloc` on this allocator with the
+    /// same `layout`, and not freed since.
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout);
+}
+
+/// Serializes access to an [`UnsyncAlloc`] with a spinlock.
+///
+/// If the inner allocator panics in the middle of an operation, its state
+/// may be inconsistent, so the wrapper is poisoned: from then on `alloc`
+/// returns null and `dealloc` leaks the block instead of touching the inner
+/// allocator again. Poisoning only happens when panics unwind.
+///
+/// # Reentrancy
+///
+/// The lock is held while the inner allocator runs, and while the closure
+/// given to [`with`](Self::with) runs. Anything that allocates from the
+/// same `Locked` in that window, such as a panic hook or a logging call
+/// made by the inner allocator, re-enters it. With the `std` feature this
+/// is detected per thread: the nested `alloc` returns null and the nested
+/// `dealloc` leaks, instead of deadlocking. Without `std` there is no way to
+/// tell re-entry from contention, and it spins forever, so code running
+/// under the lock must not allocate from it.
+///
+/// Operations on other allocators, including other `Locked` ones, are
+/// always fine from under the lock.
+#[derive(Debug)]
+pub struct Locked<A> {
+    inner: UnsafeCell<A>,
+    locked: AtomicBool,
+    poisoned: AtomicBool,
+}
+
+unsafe impl<A: Send> Sync for Locked<A> {}
+
+impl<A> Locked<A> {
+    pub const fn new(inner: A) -> Self {
+        Self {
+            inner: UnsafeCell::new(inner),
+            locked: AtomicBool::new(false),
+            poisoned: AtomicBool::new(false),
+        }
+    }
+
+    pub fn is_poisoned(&self) -> bool {
+        self.poisoned.load(Ordering::Acquire)
+    }
+
+    /// Lifts the poison flag.
+    ///
+    /// # Safety
+    ///
+    /// The inner allocator's state must be consistent again, e.g. because the
+    /// panic is known to have happened before it was modified.
+    pub unsafe fn clear_poison(&self) {
+        self.poisoned.store(false, Ordering::Release);
+    }
+
+    /// Runs `f` with exclusive access to the inner allocator, or returns
+    /// `None` if the wrapper is poisoned, or if this thread is already inside
+    /// an operation on it (see [Reentrancy](Self#reentrancy)). A panic in `f`
+    /// poisons it.
+    pub fn with<R>(&self, f: impl FnOnce(&mut A) -> R) -> Option<R> {
+        let guard = self.lock()?;
+        let result = f(unsafe { &mut *self.inner.get() });
+        guard.complete();
+        Some(result)
+    }
+
+    pub fn into_inner(self) -> A {
+        self.inner.into_inner()
+    }
+
+    fn lock(&self) -> Option<OperationGuard<'_>> {
+        #[cfg(feature = "std")]
+        if HELD.get() == self.id() {
+            return None;
+        }
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        let guard = OperationGuard {
+            locked: &self.locked,
+            poisoned: &self.poisoned,
+            completed: false,
+            #[cfg(feature = "std")]
+            previous: HELD.replace(self.id()),
+        };
+        if self.is_poisoned() {
+            guard.complete();
+            return None;
+        }
+        Some(guard)
+    }
+
+    #[cfg(feature = "std")]
+    fn id(&self) -> *const () {
+        ptr::from_ref(self).cast()
+    }
+}
+
+#[cfg(feature = "std")]
+std::thread_local! {
+    // The Locked this thread is running an operation on, if any. Const
+    // initialized, so it never allocates.
+    static HELD: core::cell::Cell<*const ()> = const { core::cell::Cell::new(ptr::null()) };
+}
+
+/// Releases the lock on drop, poisoning the wrapper unless the operation
+/// was marked complete, i.e. when dropped while unwinding.
+struct OperationGuard<'a> {
+    locked: &'a AtomicBool,
+    poisoned: &'a AtomicBool,
+    completed: bool,
+    #[cfg(feature = "std")]
+    previous: *const (),
+}
+
+impl OperationGuard<'_> {
+    fn complete(mut self) {
+        self.completed = true;
+    }
+}
+
+impl Drop for OperationGuard<'_> {
+    fn drop(&mut self) {
+        if !self.completed {
+            self.poisoned.store(true, Ordering::Release);
+        }
+        #[cfg(feature = "std")]
+        HELD.set(self.previous);
+        self.locked.store(false, Ordering::Release);
+    }
+}
+
+unsafe impl<A: UnsyncAlloc> GlobalAlloc for Locked<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.with(|inner| inner.alloc(layout))
+            .unwrap_or(ptr::null_mut())
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.with(|inner| unsafe { inner.dealloc(ptr, layout) });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::boxed::Box;
+
+    /// A bump allocator over owned memory that relies on `&mut self` instead
+    /// of atomics.
+    struct UnsyncBump {
+        memory: Box<[u8]>,
+        next: usize,
+        panic_on_size: Option<usize>,
+    }
+
+    impl UnsyncBump {
+        fn new(size: usize) -> Self {
+            Self {
+                memory: std::vec![0; size].into_boxed_slice(),
+                next: 0,
+                panic_on_size: None,
+            }
+        }
+    }
+
+    impl UnsyncAlloc for UnsyncBump {
+        fn alloc(&mut self, layout: Layout) -> *mut u8 {
+            if self.panic_on_size == Some(layout.size()) {
+                panic!("inner allocator failed mid-operation");
+            }
+            if layout.size() == 0 {
+                return crate::zero_size::dangling(layout);
+            }
+            let base = self.memory.as_mut_ptr();
+            let start = (base.addr() + self.next).next_multiple_of(layout.align()) - base.addr();
+            if start + layout.size() > self.memory.len() {
+                return ptr::null_mut();
+            }
+            self.next = start + layout.size();
+            unsafe { base.add(start) }
+        }
+
+        unsafe fn dealloc(&mut self, _ptr: *mut u8, _layout: Layout) {}
+    }
+
+    test_suite! {
+        Locked::new(UnsyncBump::new(65536)),
+        Locked::new(UnsyncBump::new(256))
+    }
+
+    #[test]
+    fn test_panic_poisons() {
+        let mut inner = UnsyncBump::new(4096);
+        inner.panic_on_size = Some(13);
+        let allocator = Locked::new(inner);
+        let layout = Layout::from_size_align(64, 8).unwrap();
+
+        unsafe {
+            let ptr = allocator.alloc(layout);
+            assert!(!ptr.is_null());
+
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                allocator.alloc(Layout::from_size_align(13, 1).unwrap())
+            }));
+            assert!(result.is_err());
+            assert!(allocator.is_poisoned());
+
+            // the lock was released, and the poisoned allocator refuses work
+            assert!(allocator.alloc(layout).is_null());
+            allocator.dealloc(ptr, layout);
+            assert!(allocator.with(|_| ()).is_none());
+
+            allocator.clear_poison();
+            assert!(!allocator.alloc(layout).is_null());
+        }
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_reentrant_alloc_does_not_deadlock() {
+        /// Allocates from the `Locked` it is wrapped in, as a logging hook
+        /// or panic handler using the global allocator would.
+        struct Reentrant {
+            nested: Option<*mut u8>,
+        }
+
+        unsafe impl Send for Reentrant {}
+
+        static ALLOCATOR: Locked<Reentrant> = Locked::new(Reentrant { nested: None });
+
+        impl UnsyncAlloc for Reentrant {
+            fn alloc(&mut self, layout: Layout) -> *mut u8 {
+                self.nested = Some(unsafe { ALLOCATOR.alloc(layout) });
+                ptr::null_mut()
+            }
+
+            unsafe fn dealloc(&mut self, _ptr: *mut u8, _layout: Layout) {}
+        }
+
+        unsafe {
+            assert!(ALLOCATOR.alloc(Layout::new::<u64>()).is_null());
+        }
+        assert_eq!(
+            ALLOCATOR.with(|inner| inner.nested),
+            Some(Some(ptr::null_mut()))
+        );
+        assert!(!ALLOCATOR.is_poisoned());
+    }
+}
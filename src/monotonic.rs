@@ -0,0 +1,206 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::Cell;
+use core::ptr;
+
+use crate::{MemoryResource, zero_size};
+
+/// A bump allocator over a caller-provided buffer that overflows into
+/// chunks from an upstream resource, like C++'s
+/// `std::pmr::monotonic_buffer_resource`.
+///
+/// The buffer can be a stack array, a static or a heap slice. Once it is
+/// full, chunks of geometrically growing size are taken from `upstream`.
+/// Nothing is freed until [`release`](Self::release) or drop, which hand
+/// every chunk back to the upstream at once.
+///
+/// The resource is not thread-safe: it is meant for short-lived,
+/// single-threaded work such as building one request's data.
+#[derive(Debug)]
+pub struct MonotonicResource<'a, U: MemoryResource + ?Sized> {
+    upstream: &'a U,
+    buffer: *mut u8,
+    buffer_len: usize,
+    // the region being bumped through, the buffer or the newest chunk
+    next_free: Cell<*mut u8>,
+    region_end: Cell<*mut u8>,
+    chunks: Cell<*mut ChunkHeader>,
+    next_chunk_size: Cell<usize>,
+}
+
+/// Starts each upstream chunk, linking the chunks for `release`.
+struct ChunkHeader {
+    next: *mut ChunkHeader,
+    layout: Layout,
+}
+
+const MIN_CHUNK_SIZE: usize = 1024;
+
+impl<'a, U: MemoryResource + ?Sized> MonotonicResource<'a, U> {
+    /// A resource with no initial buffer, taking every chunk from `upstream`.
+    pub fn new(upstream: &'a U) -> Self {
+        Self::with_buffer(&mut [], upstream)
+    }
+
+    /// A resource serving allocations from `buffer` first.
+    pub fn with_buffer(buffer: &'a mut [u8], upstream: &'a U) -> Self {
+        let range = buffer.as_mut_ptr_range();
+        Self {
+            upstream,
+            buffer: range.start,
+            buffer_len: buffer.len(),
+            next_free: Cell::new(range.start),
+            region_end: Cell::new(range.end),
+            chunks: Cell::new(ptr::null_mut()),
+            next_chunk_size: Cell::new(buffer.len().max(MIN_CHUNK_SIZE)),
+        }
+    }
+
+    /// Returns every upstream chunk and starts over from the initial buffer,
+    /// invalidating all blocks allocated so far.
+    pub fn release(&mut self) {
+        let mut chunk = self.chunks.replace(ptr::null_mut());
+        while !chunk.is_null() {
+            unsafe {
+                let ChunkHeader { next, layout } = chunk.read();
+                self.upstream.deallocate(chunk.cast(), layout);
+                chunk = next;
+            }
+        }
+        self.next_free.set(self.buffer);
+        self.region_end
+            .set(self.buffer.wrapping_add(self.buffer_len));
+        self.next_chunk_size
+            .set(self.buffer_len.max(MIN_CHUNK_SIZE));
+    }
+
+    /// Bumps through the current region, or returns null if `layout` does
+    /// not fit in what is left of it.
+    fn bump(&self, layout: Layout) -> *mut u8 {
+        let next_free = self.next_free.get();
+        let end = self.region_end.get();
+        let padding = next_free.align_offset(layout.align());
+        let available = end.addr() - next_free.addr();
+        match padding.checked_add(layout.size()) {
+            Some(needed) if needed <= available => unsafe {
+                let block = next_free.add(padding);
+                self.next_free.set(block.add(layout.size()));
+                block
+            },
+            _ => ptr::null_mut(),
+        }
+    }
+
+    /// Makes a new upstream chunk the current region, big enough for
+    /// `layout`.
+    fn grow(&self, layout: Layout) -> bool {
+        let header = Layout::new::<ChunkHeader>();
+        let Ok((min_layout, _)) = header.extend(layout) else {
+            return false;
+        };
+        let preferred = self.next_chunk_size.get().max(min_layout.size());
+        // fall back to an exact fit when the upstream can't spare a chunk of
+        // the preferred size
+        let chunk_layout = [preferred, min_layout.size()]
+            .into_iter()
+            .filter_map(|size| Layout::from_size_align(size, min_layout.align()).ok())
+            .find_map(|chunk_layout| {
+                let chunk = self.upstream.allocate(chunk_layout);
+                (!chunk.is_null()).then_some((chunk, chunk_layout))
+            });
+        let Some((chunk, chunk_layout)) = chunk_layout else {
+            return false;
+        };
+        unsafe {
+            chunk.cast::<ChunkHeader>().write(ChunkHeader {
+                next: self.chunks.get(),
+                layout: chunk_layout,
+            });
+            self.chunks.set(chunk.cast());
+            self.next_free.set(chunk.add(header.size()));
+            self.region_end.set(chunk.add(chunk_layout.size()));
+        }
+        self.next_chunk_size.set(preferred.saturating_mul(2));
+        true
+    }
+}
+
+impl<U: MemoryResource + ?Sized> Drop for MonotonicResource<'_, U> {
+    fn drop(&mut self) {
+        self.release();
+    }
+}
+
+unsafe impl<U: MemoryResource + ?Sized> GlobalAlloc for MonotonicResource<'_, U> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == 0 {
+            return zero_size::dangling(layout);
+        }
+        let block = self.bump(layout);
+        if !block.is_null() || !self.grow(layout) {
+            return block;
+        }
+        self.bump(layout)
+    }
+
+    unsafe fn dealloc(&self, _ptr: *mut u8, _layout: Layout) {}
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::BumpAllocator;
+    use crate::test_utils::Counting;
+    use std::boxed::Box;
+
+    fn leaked_buffer(size: usize) -> &'static mut [u8] {
+        Box::leak(std::vec![0; size].into_boxed_slice())
+    }
+
+    fn leaked_upstream<const SIZE: usize>() -> &'static BumpAllocator<SIZE> {
+        Box::leak(Box::new(BumpAllocator::new([0; SIZE])))
+    }
+
+    // not Sync, so no concurrency tests
+    test_suite! {
+        MonotonicResource::with_buffer(leaked_buffer(4096), leaked_upstream::<{ 1 << 18 }>()),
+        MonotonicResource::with_buffer(leaked_buffer(256), leaked_upstream::<256>());
+        integrity, realloc
+    }
+
+    #[test]
+    fn test_stack_buffer_then_upstream() {
+        let upstream = Counting::new();
+        let mut buffer = [0u8; 256];
+        let buffer_range = buffer.as_ptr_range();
+        let layout = Layout::from_size_align(64, 8).unwrap();
+
+        let mut resource = MonotonicResource::with_buffer(&mut buffer, &upstream);
+        unsafe {
+            // the buffer is used up first, without touching the upstream
+            for _ in 0..4 {
+                let ptr = resource.alloc(layout);
+                assert!(buffer_range.contains(&ptr.cast_const()));
+            }
+            assert_eq!(upstream.live(), 0);
+
+            let overflow = resource.alloc(layout);
+            assert!(!overflow.is_null());
+            assert!(!buffer_range.contains(&overflow.cast_const()));
+            assert!(upstream.live() > 0);
+
+            // a request larger than the growth step gets its own chunk
+            let big = resource.alloc(Layout::from_size_align(1 << 16, 4096).unwrap());
+            assert!(!big.is_null());
+            assert_eq!(big.addr() % 4096, 0);
+        }
+
+        resource.release();
+        assert_eq!(upstream.live(), 0);
+        unsafe {
+            let ptr = resource.alloc(layout);
+            assert_eq!(ptr.cast_const(), buffer_range.start);
+        }
+        drop(resource);
+        assert_eq!(upstream.live(), 0);
+    }
+}
@@ -0,0 +1,30 @@
+//! MemorySanitizer shadow updates, so MSan reports reads of heap memory that
+//! was never written since it was allocated, or that was already freed.
+//!
+//! The hooks only do something when the crate is built with
+//! `RUSTFLAGS="-Zsanitizer=memory"` on nightly, which sets
+//! `cfg(sanitize_memory)` from the build script. Otherwise they are no-ops.
+
+#[cfg(sanitize_memory)]
+unsafe extern "C" {
+    fn __msan_allocated_memory(data: *const u8, size: usize);
+    fn __msan_poison(addr: *const u8, size: usize);
+}
+
+/// Marks a freshly allocated block as uninitialized, whatever the heap held
+/// at that address before.
+pub(crate) fn allocated(_ptr: *const u8, _size: usize) {
+    #[cfg(sanitize_memory)]
+    unsafe {
+        __msan_allocated_memory(_ptr, _size)
+    }
+}
+
+/// Marks a freed block as uninitialized, so reads through dangling pointers
+/// are reported.
+pub(crate) fn poison(_ptr: *const u8, _size: usize) {
+    #[cfg(sanitize_memory)]
+    unsafe {
+        __msan_poison(_ptr, _size)
+    }
+}
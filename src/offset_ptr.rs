@@ -0,0 +1,263 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::{Deref, DerefMut};
+use core::ptr::{self, NonNull};
+
+use crate::SharedMemAllocator;
+
+/// A pointer into a shared memory segment, stored as an offset from the
+/// segment base so it stays valid in every process mapping the segment.
+///
+/// An `OffsetPtr` can itself live inside the segment, which is how shared
+/// data structures link to each other. It must be resolved against the
+/// process-local [`SharedMemAllocator`] before use.
+#[repr(transparent)]
+pub struct OffsetPtr<T> {
+    offset: u64,
+    _marker: PhantomData<*mut T>,
+}
+
+// Zero is the segment header, which is never handed out, so it doubles as null.
+const NULL: u64 = 0;
+
+impl<T> OffsetPtr<T> {
+    pub const fn null() -> Self {
+        Self::from_offset(NULL)
+    }
+
+    pub const fn from_offset(offset: u64) -> Self {
+        Self {
+            offset,
+            _marker: PhantomData,
+        }
+    }
+
+    pub const fn offset(self) -> u64 {
+        self.offset
+    }
+
+    pub const fn is_null(self) -> bool {
+        self.offset == NULL
+    }
+
+    /// Resolves the offset in this process's mapping of the segment.
+    pub fn resolve(self, segment: &SharedMemAllocator) -> *mut T {
+        if self.is_null() {
+            ptr::null_mut()
+        } else if size_of::<T>() == 0 {
+            NonNull::dangling().as_ptr()
+        } else {
+            segment.ptr_at(self.offset as usize).cast()
+        }
+    }
+}
+
+impl SharedMemAllocator {
+    /// Converts a pointer into this mapping of the segment to an
+    /// [`OffsetPtr`]. Null stays null.
+    pub fn offset_ptr<T>(&self, ptr: *const T) -> OffsetPtr<T> {
+        if ptr.is_null() {
+            OffsetPtr::null()
+        } else {
+            OffsetPtr::from_offset(self.offset_of(ptr.cast()) as u64)
+        }
+    }
+}
+
+impl<T> Clone for OffsetPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for OffsetPtr<T> {}
+
+impl<T> PartialEq for OffsetPtr<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.offset == other.offset
+    }
+}
+
+impl<T> Eq for OffsetPtr<T> {}
+
+impl<T> fmt::Debug for OffsetPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "OffsetPtr({:#x})", self.offset)
+    }
+}
+
+unsafe impl<T: Send> Send for OffsetPtr<T> {}
+unsafe impl<T: Sync> Sync for OffsetPtr<T> {}
+
+/// An owned value allocated in a shared memory segment.
+///
+/// The value is dropped and its memory returned to the segment when the box
+/// is dropped. Use [`into_offset_ptr`](Self::into_offset_ptr) to hand
+/// ownership to another process, which takes it back with
+/// [`from_offset_ptr`](Self::from_offset_ptr).
+///
+/// `T` is shared byte-for-byte between processes, so it must not contain
+/// process-local pointers; link shared values with [`OffsetPtr`] instead.
+pub struct ShmBox<'a, T> {
+    ptr: OffsetPtr<T>,
+    segment: &'a SharedMemAllocator,
+}
+
+impl<'a, T> ShmBox<'a, T> {
+    /// Moves `value` into the segment, handing it back if the segment is
+    /// out of memory.
+    pub fn new_in(value: T, segment: &'a SharedMemAllocator) -> Result<Self, T> {
+        let ptr = if size_of::<T>() == 0 {
+            // any non-null offset will do, zero-size values are never read
+            OffsetPtr::from_offset(1)
+        } else {
+            let raw = unsafe { segment.alloc(Layout::new::<T>()) }.cast::<T>();
+            if raw.is_null() {
+                return Err(value);
+            }
+            segment.offset_ptr(raw)
+        };
+        unsafe { ptr.resolve(segment).write(value) };
+        Ok(Self { ptr, segment })
+    }
+
+    /// Takes ownership of a value previously released with
+    /// [`into_offset_ptr`](Self::into_offset_ptr).
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must come from `into_offset_ptr` on a box in the same segment,
+    /// and ownership must not have been reclaimed already.
+    pub unsafe fn from_offset_ptr(ptr: OffsetPtr<T>, segment: &'a SharedMemAllocator) -> Self {
+        Self { ptr, segment }
+    }
+
+    /// Releases ownership without dropping the value, returning its location
+    /// in the segment.
+    pub fn into_offset_ptr(self) -> OffsetPtr<T> {
+        let ptr = self.ptr;
+        core::mem::forget(self);
+        ptr
+    }
+
+    pub fn offset_ptr(&self) -> OffsetPtr<T> {
+        self.ptr
+    }
+}
+
+impl<T> Deref for ShmBox<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.ptr.resolve(self.segment) }
+    }
+}
+
+impl<T> DerefMut for ShmBox<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.ptr.resolve(self.segment) }
+    }
+}
+
+impl<T> Drop for ShmBox<'_, T> {
+    fn drop(&mut self) {
+        let raw = self.ptr.resolve(self.segment);
+        unsafe {
+            ptr::drop_in_place(raw);
+            if size_of::<T>() != 0 {
+                self.segment.dealloc(raw.cast(), Layout::new::<T>());
+            }
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for ShmBox<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ffi::CString;
+
+    struct Node {
+        value: u32,
+        next: OffsetPtr<Node>,
+    }
+
+    fn segment_name(test: &str) -> CString {
+        CString::new(std::format!("/simple-alloc-{test}-{}", std::process::id())).unwrap()
+    }
+
+    #[test]
+    fn test_offset_ptr_across_mappings() {
+        let name = segment_name("offset-ptr");
+        let a = SharedMemAllocator::create(&name, 4096).unwrap();
+        let b = SharedMemAllocator::open(&name).unwrap();
+        SharedMemAllocator::unlink(&name).unwrap();
+
+        let tail = ShmBox::new_in(
+            Node {
+                value: 2,
+                next: OffsetPtr::null(),
+            },
+            &a,
+        )
+        .ok()
+        .unwrap();
+        let head = ShmBox::new_in(
+            Node {
+                value: 1,
+                next: tail.into_offset_ptr(),
+            },
+            &a,
+        )
+        .ok()
+        .unwrap();
+
+        // walk the list through the other mapping
+        let mut values = std::vec::Vec::new();
+        let mut node = head.offset_ptr();
+        while !node.is_null() {
+            let resolved = unsafe { &*node.resolve(&b) };
+            values.push(resolved.value);
+            node = resolved.next;
+        }
+        assert_eq!(values, [1, 2]);
+
+        // the second mapping takes ownership of the tail and frees it
+        let tail = unsafe { ShmBox::from_offset_ptr(head.next, &b) };
+        assert_eq!(tail.value, 2);
+        drop(tail);
+        drop(head);
+
+        // all blocks are back, so one allocation can span the whole heap
+        let big = ShmBox::new_in([0u8; 3072], &a);
+        assert!(big.is_ok());
+    }
+
+    #[test]
+    fn test_shm_box_out_of_memory_returns_value() {
+        let name = segment_name("shm-box-oom");
+        let segment = SharedMemAllocator::create(&name, 256).unwrap();
+        SharedMemAllocator::unlink(&name).unwrap();
+
+        let value = [7u8; 1024];
+        let returned = ShmBox::new_in(value, &segment).unwrap_err();
+        assert_eq!(returned, value);
+    }
+
+    #[test]
+    fn test_shm_box_zero_size() {
+        let name = segment_name("shm-box-zst");
+        let segment = SharedMemAllocator::create(&name, 256).unwrap();
+        SharedMemAllocator::unlink(&name).unwrap();
+
+        let unit = ShmBox::new_in((), &segment).unwrap();
+        assert!(!unit.offset_ptr().is_null());
+        assert_eq!(*unit, ());
+    }
+}
@@ -0,0 +1,125 @@
+use core::alloc::Layout;
+use core::fmt;
+#[cfg(feature = "debug-oom")]
+use core::cell::UnsafeCell;
+#[cfg(feature = "debug-oom")]
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// What an allocator does when it cannot satisfy a request.
+#[derive(Debug, Clone, Copy)]
+pub enum OomPolicy {
+    /// Return a null pointer, as `GlobalAlloc` allows.
+    ReturnNull,
+    /// Print the failing layout and the heap usage at the time through the
+    /// panic hook, then abort the process. The panic doesn't unwind, since
+    /// unwinding out of a global allocator is undefined behavior.
+    Panic,
+    /// Call the handler and retry the allocation for as long as it returns
+    /// `true`. Returning `false` gives up and returns null.
+    Retry(fn(Layout) -> bool),
+}
+
+impl OomPolicy {
+    /// Applies the policy to a failed allocation, returning whether the
+    /// allocation should be attempted again.
+    pub(crate) fn should_retry(&self, layout: Layout, used: usize, capacity: usize) -> bool {
+        #[cfg(feature = "tracing")]
+        crate::traced::emit(|| {
+            tracing::warn!(
+                size = layout.size(),
+                align = layout.align(),
+                used,
+                capacity,
+                "out of memory"
+            )
+        });
+        match self {
+            OomPolicy::ReturnNull => false,
+            OomPolicy::Panic => out_of_memory(layout.size(), layout.align(), used, capacity),
+            OomPolicy::Retry(handler) => handler(layout),
+        }
+    }
+}
+
+/// Panics without unwinding, which aborts once the panic hook has run: a
+/// panic can't unwind out of an `extern "C"` function.
+#[cold]
+extern "C" fn out_of_memory(size: usize, align: usize, used: usize, capacity: usize) -> ! {
+    panic!(
+        "out of memory: failed to allocate {size} bytes (align {align}), {used}/{capacity} bytes in use"
+    );
+}
+
+/// Returned by fallible allocation methods that report failure with a
+/// `Result` rather than a null pointer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocError;
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("memory allocation failed")
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for AllocError {}
+
+/// The last allocation failure of an allocator, kept when the `debug-oom`
+/// feature is enabled so an `alloc_error_handler` can report more than the
+/// layout it is given.
+#[cfg(feature = "debug-oom")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OomInfo {
+    pub layout: Layout,
+    pub used: usize,
+    pub capacity: usize,
+    /// Size of the largest block with the failed request's alignment that
+    /// could still have been allocated.
+    pub largest_free: usize,
+}
+
+#[cfg(feature = "debug-oom")]
+#[derive(Debug)]
+pub(crate) struct LastOom {
+    locked: AtomicBool,
+    info: UnsafeCell<Option<OomInfo>>,
+}
+
+#[cfg(feature = "debug-oom")]
+unsafe impl Sync for LastOom {}
+
+#[cfg(feature = "debug-oom")]
+impl LastOom {
+    pub(crate) const fn new() -> Self {
+        Self {
+            locked: AtomicBool::new(false),
+            info: UnsafeCell::new(None),
+        }
+    }
+
+    /// Records a failure. If another thread is recording one at the same
+    /// time, this one is dropped: either is an equally good "last" failure.
+    pub(crate) fn record(&self, info: OomInfo) {
+        if self
+            .locked
+            .compare_exchange(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_ok()
+        {
+            unsafe { *self.info.get() = Some(info) };
+            self.locked.store(false, Ordering::Release);
+        }
+    }
+
+    pub(crate) fn get(&self) -> Option<OomInfo> {
+        while self
+            .locked
+            .compare_exchange_weak(false, true, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        let info = unsafe { *self.info.get() };
+        self.locked.store(false, Ordering::Release);
+        info
+    }
+}
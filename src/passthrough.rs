@@ -0,0 +1,127 @@
+use core::alloc::{GlobalAlloc, Layout};
+
+/// Routes allocations larger than `THRESHOLD` bytes to an upstream allocator
+/// and everything else to the main heap.
+///
+/// Routing only depends on the layout size, so `dealloc` always reaches the
+/// allocator that served the block.
+#[derive(Debug)]
+pub struct Passthrough<A, U, const THRESHOLD: usize> {
+    heap: A,
+    upstream: U,
+}
+
+impl<A, U, const THRESHOLD: usize> Passthrough<A, U, THRESHOLD> {
+    pub const fn new(heap: A, upstream: U) -> Self {
+        Self { heap, upstream }
+    }
+
+    pub fn heap(&self) -> &A {
+        &self.heap
+    }
+
+    pub fn upstream(&self) -> &U {
+        &self.upstream
+    }
+
+    fn is_large(size: usize) -> bool {
+        size > THRESHOLD
+    }
+}
+
+unsafe impl<A: GlobalAlloc, U: GlobalAlloc, const THRESHOLD: usize> GlobalAlloc
+    for Passthrough<A, U, THRESHOLD>
+{
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if Self::is_large(layout.size()) {
+            unsafe { self.upstream.alloc(layout) }
+        } else {
+            unsafe { self.heap.alloc(layout) }
+        }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if Self::is_large(layout.size()) {
+            unsafe { self.upstream.dealloc(ptr, layout) }
+        } else {
+            unsafe { self.heap.dealloc(ptr, layout) }
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        match (Self::is_large(layout.size()), Self::is_large(new_size)) {
+            (false, false) => unsafe { self.heap.realloc(ptr, layout, new_size) },
+            (true, true) => unsafe { self.upstream.realloc(ptr, layout, new_size) },
+            _ => {
+                let new_layout =
+                    unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+                let new_ptr = unsafe { self.alloc(new_layout) };
+                if !new_ptr.is_null() {
+                    unsafe {
+                        new_ptr.copy_from_nonoverlapping(ptr, layout.size().min(new_size));
+                        self.dealloc(ptr, layout);
+                    }
+                }
+                new_ptr
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::BumpAllocator;
+
+    test_suite! {
+        Passthrough::<_, _, 1024>::new(BumpAllocator::new([0; 65536]), BumpAllocator::new([0; 65536])),
+        Passthrough::<_, _, 1024>::new(BumpAllocator::new([0; 256]), BumpAllocator::new([0; 256]))
+    }
+
+    #[test]
+    fn test_large_allocation_goes_upstream() {
+        let allocator = Passthrough::<_, _, 128>::new(
+            BumpAllocator::new([0; 256]),
+            BumpAllocator::new([0; 4096]),
+        );
+
+        unsafe {
+            let layout = Layout::from_size_align(1024, 8).unwrap();
+            let ptr = allocator.alloc(layout);
+            assert!(!ptr.is_null(), "large allocation was not routed upstream");
+
+            // the small heap is still untouched
+            let small_layout = Layout::from_size_align(256, 8).unwrap();
+            let small = allocator.alloc(small_layout);
+            assert!(!small.is_null());
+
+            allocator.dealloc(small, small_layout);
+            allocator.dealloc(ptr, layout);
+        }
+    }
+
+    #[test]
+    fn test_realloc_across_threshold() {
+        let allocator = Passthrough::<_, _, 128>::new(
+            BumpAllocator::new([0; 4096]),
+            BumpAllocator::new([0; 4096]),
+        );
+
+        unsafe {
+            let layout = Layout::from_size_align(64, 8).unwrap();
+            let ptr = allocator.alloc(layout);
+            assert!(!ptr.is_null());
+            ptr.write_bytes(0xAB, 64);
+
+            let grown = allocator.realloc(ptr, layout, 512);
+            assert!(!grown.is_null());
+            assert!(
+                std::slice::from_raw_parts(grown, 64)
+                    .iter()
+                    .all(|&b| b == 0xAB)
+            );
+
+            allocator.dealloc(grown, Layout::from_size_align(512, 8).unwrap());
+        }
+    }
+}
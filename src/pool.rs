@@ -0,0 +1,432 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::Cell;
+use core::fmt;
+use core::ptr;
+
+use crate::{MemoryResource, zero_size};
+
+/// Pools of fixed-size blocks, one per power-of-two size class, refilled
+/// from an upstream resource, like C++'s
+/// `std::pmr::unsynchronized_pool_resource`.
+///
+/// Freed blocks go back to their class's free list and are reused for the
+/// next request of that class. Requests above the largest class are passed
+/// straight to the upstream. Pool chunks go back upstream on drop, or
+/// earlier through [`trim`](Self::trim) once all their blocks are free.
+///
+/// There is no synchronization at all, so the resource is not `Sync`; it is
+/// meant for single-threaded parsers and codecs.
+///
+/// With the `checksum` feature, every free-list link and chunk footer
+/// carries a checksum, verified whenever it is read. A stray write or bit
+/// flip in the pool's metadata then aborts the process, after printing where
+/// it was found, instead of handing out or freeing the wrong memory. The
+/// feature only covers this resource: the other allocators in the crate keep
+/// their metadata unchecked.
+#[derive(Debug)]
+pub struct UnsyncPoolResource<'a, U: MemoryResource + ?Sized> {
+    upstream: &'a U,
+    free_lists: [Cell<*mut FreeBlock>; CLASS_COUNT],
+    chunks: Cell<*mut ChunkFooter>,
+}
+
+// Blocks are at least 16 bytes, so there is room for the checksum.
+struct FreeBlock {
+    next: *mut FreeBlock,
+    #[cfg(feature = "checksum")]
+    check: usize,
+}
+
+impl FreeBlock {
+    fn new(next: *mut FreeBlock) -> Self {
+        Self {
+            next,
+            #[cfg(feature = "checksum")]
+            check: checksum(&[next.addr()]),
+        }
+    }
+
+    fn next(&self) -> *mut FreeBlock {
+        #[cfg(feature = "checksum")]
+        if self.check != checksum(&[self.next.addr()]) {
+            corrupted(Metadata::FreeList, ptr::from_ref(self).cast());
+        }
+        self.next
+    }
+}
+
+/// Ends each chunk, after its blocks, linking the chunks for drop.
+struct ChunkFooter {
+    next: *mut ChunkFooter,
+    base: *mut u8,
+    layout: Layout,
+    #[cfg(feature = "checksum")]
+    check: usize,
+}
+
+impl ChunkFooter {
+    fn new(next: *mut ChunkFooter, base: *mut u8, layout: Layout) -> Self {
+        Self {
+            next,
+            base,
+            layout,
+            #[cfg(feature = "checksum")]
+            check: checksum(&[next.addr(), base.addr(), layout.size(), layout.align()]),
+        }
+    }
+
+    /// Reads the footer at `footer`, checking it wasn't overwritten.
+    unsafe fn read(footer: *mut ChunkFooter) -> Self {
+        let this = unsafe { footer.read() };
+        #[cfg(feature = "checksum")]
+        if this.check
+            != checksum(&[
+                this.next.addr(),
+                this.base.addr(),
+                this.layout.size(),
+                this.layout.align(),
+            ])
+        {
+            corrupted(Metadata::ChunkFooter, footer.cast_const().cast());
+        }
+        this
+    }
+}
+
+/// Prints where the corruption was found through the panic hook, then
+/// aborts: the checks run inside `GlobalAlloc` methods and `drop`, which must
+/// not unwind, and a panic can't unwind out of an `extern "C"` function.
+#[cfg(feature = "checksum")]
+#[cold]
+extern "C" fn corrupted(metadata: Metadata, at: *const u8) -> ! {
+    let metadata = match metadata {
+        Metadata::FreeList => "free list",
+        Metadata::ChunkFooter => "chunk footer",
+    };
+    panic!("pool {metadata} corrupted at {at:p}");
+}
+
+#[cfg(feature = "checksum")]
+#[repr(u8)]
+#[derive(Clone, Copy)]
+enum Metadata {
+    FreeList,
+    ChunkFooter,
+}
+
+/// Mixes `words` so that a flipped bit or an overwritten word changes the
+/// result, and a zeroed block doesn't check out.
+#[cfg(feature = "checksum")]
+fn checksum(words: &[usize]) -> usize {
+    const SEED: u64 = 0x9e37_79b9_7f4a_7c15;
+    words.iter().fold(SEED as usize, |sum, &word| {
+        (sum ^ word).wrapping_mul(SEED as usize | 1).rotate_left(17)
+    })
+}
+
+const MIN_CLASS_SHIFT: u32 = 4;
+const MAX_CLASS_SHIFT: u32 = 11;
+const CLASS_COUNT: usize = (MAX_CLASS_SHIFT - MIN_CLASS_SHIFT + 1) as usize;
+const BLOCKS_PER_CHUNK: usize = 16;
+
+/// The size class serving `layout`, or `None` if it is too large for the
+/// pools. Blocks are aligned to their size, so the class also covers the
+/// alignment.
+fn class_of(layout: Layout) -> Option<usize> {
+    let size = layout.size().max(layout.align()).next_power_of_two();
+    let shift = size.trailing_zeros().max(MIN_CLASS_SHIFT);
+    (shift <= MAX_CLASS_SHIFT).then(|| (shift - MIN_CLASS_SHIFT) as usize)
+}
+
+fn class_size(class: usize) -> usize {
+    1 << (class as u32 + MIN_CLASS_SHIFT)
+}
+
+impl<'a, U: MemoryResource + ?Sized> UnsyncPoolResource<'a, U> {
+    pub fn new(upstream: &'a U) -> Self {
+        Self {
+            upstream,
+            free_lists: [const { Cell::new(ptr::null_mut()) }; CLASS_COUNT],
+            chunks: Cell::new(ptr::null_mut()),
+        }
+    }
+
+    /// Writes the pools' state as a JSON object, for bug reports: the number
+    /// of chunks taken from the upstream and, per size class, the number of
+    /// free blocks.
+    pub fn dump_state(&self, out: &mut impl fmt::Write) -> fmt::Result {
+        let mut chunks = 0;
+        let mut chunk = self.chunks.get();
+        while !chunk.is_null() {
+            chunks += 1;
+            chunk = unsafe { ChunkFooter::read(chunk) }.next;
+        }
+        write!(out, r#"{{"allocator":"pool","chunks":{chunks},"classes":["#)?;
+        for class in 0..CLASS_COUNT {
+            let free = self.count_free(class, |_| true);
+            let separator = if class == 0 { "" } else { "," };
+            write!(
+                out,
+                r#"{separator}{{"size":{},"free":{free}}}"#,
+                class_size(class)
+            )?;
+        }
+        out.write_str("]}")
+    }
+
+    /// Takes a chunk from the upstream and adds its blocks to the free list
+    /// of `class`.
+    fn refill(&self, class: usize) -> bool {
+        let block_size = class_size(class);
+        let blocks_size = block_size * BLOCKS_PER_CHUNK;
+        let Ok(layout) =
+            Layout::from_size_align(blocks_size + size_of::<ChunkFooter>(), block_size)
+        else {
+            return false;
+        };
+        let base = self.upstream.allocate(layout);
+        if base.is_null() {
+            return false;
+        }
+        unsafe {
+            let footer = base.add(blocks_size).cast::<ChunkFooter>();
+            footer.write(ChunkFooter::new(self.chunks.get(), base, layout));
+            self.chunks.set(footer);
+            for i in (0..BLOCKS_PER_CHUNK).rev() {
+                self.push(class, base.add(i * block_size));
+            }
+        }
+        true
+    }
+
+    fn push(&self, class: usize, block: *mut u8) {
+        let block = block.cast::<FreeBlock>();
+        unsafe { block.write(FreeBlock::new(self.free_lists[class].get())) };
+        self.free_lists[class].set(block);
+    }
+
+    fn pop(&self, class: usize) -> *mut u8 {
+        let block = self.free_lists[class].get();
+        if !block.is_null() {
+            self.free_lists[class].set(unsafe { (*block).next() });
+        }
+        block.cast()
+    }
+
+    /// Returns every chunk whose blocks are all free to the upstream, and
+    /// the number of bytes released. Call it when idle to shrink the
+    /// footprint after a burst of allocations.
+    pub fn trim(&self) -> usize {
+        let mut released = 0;
+        let mut prev: Option<*mut ChunkFooter> = None;
+        let mut chunk = self.chunks.get();
+        while !chunk.is_null() {
+            let ChunkFooter {
+                next, base, layout, ..
+            } = unsafe { ChunkFooter::read(chunk) };
+            let blocks = base.addr()..chunk.addr();
+            // blocks are as large as the chunk's alignment
+            let class = class_of(Layout::from_size_align(1, layout.align()).unwrap()).unwrap();
+            let is_in_chunk = |block: *mut FreeBlock| blocks.contains(&block.addr());
+
+            if self.count_free(class, is_in_chunk) == BLOCKS_PER_CHUNK {
+                self.remove_free(class, is_in_chunk);
+                match prev {
+                    Some(prev) => unsafe {
+                        let ChunkFooter { base, layout, .. } = ChunkFooter::read(prev);
+                        prev.write(ChunkFooter::new(next, base, layout));
+                    },
+                    None => self.chunks.set(next),
+                }
+                unsafe { self.upstream.deallocate(base, layout) };
+                released += layout.size();
+            } else {
+                prev = Some(chunk);
+            }
+            chunk = next;
+        }
+        #[cfg(feature = "tracing")]
+        crate::traced::emit(|| tracing::debug!(released, "pool trimmed"));
+        released
+    }
+
+    fn count_free(&self, class: usize, filter: impl Fn(*mut FreeBlock) -> bool) -> usize {
+        let mut count = 0;
+        let mut block = self.free_lists[class].get();
+        while !block.is_null() {
+            count += filter(block) as usize;
+            block = unsafe { (*block).next() };
+        }
+        count
+    }
+
+    /// Unlinks the free blocks of `class` matching `filter`.
+    fn remove_free(&self, class: usize, filter: impl Fn(*mut FreeBlock) -> bool) {
+        let mut prev: *mut FreeBlock = ptr::null_mut();
+        let mut block = self.free_lists[class].get();
+        while !block.is_null() {
+            let next = unsafe { (*block).next() };
+            if !filter(block) {
+                prev = block;
+            } else if prev.is_null() {
+                self.free_lists[class].set(next);
+            } else {
+                unsafe { prev.write(FreeBlock::new(next)) };
+            }
+            block = next;
+        }
+    }
+}
+
+impl<U: MemoryResource + ?Sized> Drop for UnsyncPoolResource<'_, U> {
+    fn drop(&mut self) {
+        let mut chunk = self.chunks.get();
+        while !chunk.is_null() {
+            unsafe {
+                let ChunkFooter {
+                    next, base, layout, ..
+                } = ChunkFooter::read(chunk);
+                self.upstream.deallocate(base, layout);
+                chunk = next;
+            }
+        }
+    }
+}
+
+unsafe impl<U: MemoryResource + ?Sized> GlobalAlloc for UnsyncPoolResource<'_, U> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == 0 {
+            return zero_size::dangling(layout);
+        }
+        let Some(class) = class_of(layout) else {
+            return self.upstream.allocate(layout);
+        };
+        let block = self.pop(class);
+        if !block.is_null() || !self.refill(class) {
+            return block;
+        }
+        self.pop(class)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if zero_size::is_dangling(ptr, layout) {
+            return;
+        }
+        match class_of(layout) {
+            Some(class) => self.push(class, ptr),
+            None => unsafe { self.upstream.deallocate(ptr, layout) },
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::BumpAllocator;
+    use crate::test_utils::Counting;
+    use std::boxed::Box;
+
+    fn leaked_upstream<const SIZE: usize>() -> &'static BumpAllocator<SIZE> {
+        Box::leak(Box::new(BumpAllocator::new([0; SIZE])))
+    }
+
+    // not Sync, so no concurrency tests
+    test_suite! {
+        UnsyncPoolResource::new(leaked_upstream::<{ 1 << 18 }>()),
+        UnsyncPoolResource::new(leaked_upstream::<2048>());
+        integrity, realloc
+    }
+
+    #[test]
+    fn test_blocks_are_reused_per_class() {
+        let upstream = Counting::new();
+        let pool = UnsyncPoolResource::new(&upstream);
+        let small = Layout::from_size_align(24, 8).unwrap();
+        let other = Layout::from_size_align(100, 4).unwrap();
+
+        unsafe {
+            let a = pool.alloc(small);
+            let chunked = upstream.live();
+            pool.dealloc(a, small);
+
+            // a different class gets its own chunk
+            let b = pool.alloc(other);
+            assert!(upstream.live() > chunked);
+            assert_eq!(b.addr() % 128, 0);
+
+            // the freed block comes back without touching the upstream
+            let live = upstream.live();
+            assert_eq!(pool.alloc(small), a);
+            assert_eq!(upstream.live(), live);
+        }
+    }
+
+    #[test]
+    fn test_dump_state() {
+        let upstream = Counting::new();
+        let pool = UnsyncPoolResource::new(&upstream);
+        unsafe { pool.alloc(Layout::from_size_align(16, 8).unwrap()) };
+
+        let mut out = std::string::String::new();
+        pool.dump_state(&mut out).unwrap();
+        assert_eq!(
+            out,
+            concat!(
+                r#"{"allocator":"pool","chunks":1,"classes":["#,
+                r#"{"size":16,"free":15},{"size":32,"free":0},{"size":64,"free":0},"#,
+                r#"{"size":128,"free":0},{"size":256,"free":0},{"size":512,"free":0},"#,
+                r#"{"size":1024,"free":0},{"size":2048,"free":0}]}"#,
+            )
+        );
+    }
+
+    #[test]
+    fn test_trim_releases_free_chunks() {
+        let upstream = Counting::new();
+        let pool = UnsyncPoolResource::new(&upstream);
+        let layout = Layout::from_size_align(32, 8).unwrap();
+        let chunk_size;
+
+        unsafe {
+            // two chunks' worth of blocks, then free all of the first chunk
+            let blocks =
+                std::vec::Vec::from_iter((0..2 * BLOCKS_PER_CHUNK).map(|_| pool.alloc(layout)));
+            chunk_size = upstream.live() / 2;
+            for &block in &blocks[..BLOCKS_PER_CHUNK] {
+                pool.dealloc(block, layout);
+            }
+            pool.dealloc(blocks[BLOCKS_PER_CHUNK], layout);
+
+            assert_eq!(pool.trim(), chunk_size);
+            assert_eq!(upstream.live(), chunk_size);
+            assert_eq!(pool.trim(), 0);
+
+            // the partly used chunk still serves its free block
+            assert_eq!(pool.alloc(layout), blocks[BLOCKS_PER_CHUNK]);
+            for &block in &blocks[BLOCKS_PER_CHUNK..] {
+                pool.dealloc(block, layout);
+            }
+        }
+        assert_eq!(pool.trim(), chunk_size);
+        assert_eq!(upstream.live(), 0);
+    }
+
+    #[test]
+    fn test_large_blocks_pass_through() {
+        let upstream = Counting::new();
+        let pool = UnsyncPoolResource::new(&upstream);
+        let large = Layout::from_size_align(8192, 8).unwrap();
+
+        unsafe {
+            let ptr = pool.alloc(large);
+            assert!(!ptr.is_null());
+            assert_eq!(upstream.live(), 8192);
+            pool.dealloc(ptr, large);
+            assert_eq!(upstream.live(), 0);
+
+            pool.alloc(Layout::from_size_align(64, 8).unwrap());
+        }
+        drop(pool);
+        assert_eq!(upstream.live(), 0);
+    }
+}
@@ -0,0 +1,216 @@
+use core::cell::UnsafeCell;
+use core::mem::MaybeUninit;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use crate::{Align2MiB, Align16, Align64, Align4096};
+
+/// A static block of `N` bytes aligned to `ALIGN`, to back a
+/// [`Heap`](crate::Heap) placed in a particular memory bank. Declare one
+/// with [`heap_region!`](crate::heap_region).
+///
+/// The bytes live in a separate [`RegionStorage`] static, which is left
+/// uninitialized, so it can go in a `NOLOAD` section and cost nothing in
+/// the image. The `HeapRegion` itself holds the flag recording whether the
+/// bytes were handed out, and stays in regular initialized memory. The bytes
+/// can only be reached through [`claim`](Self::claim), once, which is what
+/// makes [`Heap::init_region`](crate::Heap::init_region) safe.
+#[derive(Debug)]
+pub struct HeapRegion<const N: usize, const ALIGN: usize>
+where
+    ConstAlign<ALIGN>: SupportedAlign,
+{
+    storage: &'static RegionStorage<N, ALIGN>,
+    claimed: AtomicBool,
+}
+
+impl<const N: usize, const ALIGN: usize> HeapRegion<N, ALIGN>
+where
+    ConstAlign<ALIGN>: SupportedAlign,
+{
+    /// # Safety
+    ///
+    /// `storage` must not be used by any other `HeapRegion`.
+    pub const unsafe fn new(storage: &'static RegionStorage<N, ALIGN>) -> Self {
+        Self {
+            storage,
+            claimed: AtomicBool::new(false),
+        }
+    }
+
+    pub const fn size(&self) -> usize {
+        N
+    }
+
+    /// A pointer to the region's `N` bytes, the first time it is called, and
+    /// `None` after, so whoever claims the region has it to themselves.
+    pub fn claim(&self) -> Option<*mut u8> {
+        if self.claimed.swap(true, Ordering::Relaxed) {
+            return None;
+        }
+        Some(self.storage.bytes.get().cast())
+    }
+}
+
+/// The bytes of a [`HeapRegion`], in the static that
+/// [`heap_region!`](crate::heap_region) places in the link section.
+#[derive(Debug)]
+#[repr(C)]
+pub struct RegionStorage<const N: usize, const ALIGN: usize>
+where
+    ConstAlign<ALIGN>: SupportedAlign,
+{
+    _align: [<ConstAlign<ALIGN> as SupportedAlign>::Marker; 0],
+    bytes: UnsafeCell<[MaybeUninit<u8>; N]>,
+}
+
+// only reachable through the HeapRegion that claims it
+unsafe impl<const N: usize, const ALIGN: usize> Sync for RegionStorage<N, ALIGN> where
+    ConstAlign<ALIGN>: SupportedAlign
+{
+}
+
+impl<const N: usize, const ALIGN: usize> RegionStorage<N, ALIGN>
+where
+    ConstAlign<ALIGN>: SupportedAlign,
+{
+    pub const fn new() -> Self {
+        Self {
+            _align: [],
+            bytes: UnsafeCell::new([MaybeUninit::uninit(); N]),
+        }
+    }
+}
+
+impl<const N: usize, const ALIGN: usize> Default for RegionStorage<N, ALIGN>
+where
+    ConstAlign<ALIGN>: SupportedAlign,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The `ALIGN` parameter of a [`HeapRegion`].
+#[derive(Debug, Clone, Copy)]
+pub struct ConstAlign<const ALIGN: usize>;
+
+/// Implemented for the alignments a [`HeapRegion`] supports: those of the
+/// `Align*` marker types.
+pub trait SupportedAlign {
+    type Marker: Copy + core::fmt::Debug;
+}
+
+impl SupportedAlign for ConstAlign<16> {
+    type Marker = Align16;
+}
+
+impl SupportedAlign for ConstAlign<64> {
+    type Marker = Align64;
+}
+
+impl SupportedAlign for ConstAlign<4096> {
+    type Marker = Align4096;
+}
+
+impl SupportedAlign for ConstAlign<{ 2 << 20 }> {
+    type Marker = Align2MiB;
+}
+
+/// Declares a [`HeapRegion`] static whose bytes go in the given link
+/// section, e.g. a RAM bank that the linker script maps to `.axisram`:
+///
+/// ```no_run
+/// use simple_alloc::{Heap, heap_region};
+///
+/// heap_region! {
+///     #[link_section = ".axisram"]
+///     static AXISRAM: HeapRegion<{ 64 * 1024 }, 4096>;
+/// }
+///
+/// #[global_allocator]
+/// static HEAP: Heap = Heap::empty();
+///
+/// fn main() {
+///     HEAP.init_region(&AXISRAM);
+/// }
+/// ```
+///
+/// The section must be writable, and can be marked `NOLOAD` since the bytes
+/// have no initial contents. Only the bytes go there: the region's claim
+/// flag stays in regular memory.
+#[macro_export]
+macro_rules! heap_region {
+    (
+        #[link_section = $section:literal]
+        $(#[$attr:meta])*
+        $vis:vis static $name:ident: HeapRegion<$size:tt, $align:tt>;
+    ) => {
+        $(#[$attr])*
+        $vis static $name: $crate::HeapRegion<$size, $align> = {
+            #[unsafe(link_section = $section)]
+            static STORAGE: $crate::RegionStorage<$size, $align> = $crate::RegionStorage::new();
+            // STORAGE is only visible here
+            unsafe { $crate::HeapRegion::new(&STORAGE) }
+        };
+    };
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Heap;
+    extern crate std;
+    use core::alloc::{GlobalAlloc, Layout};
+
+    #[test]
+    fn test_region_is_aligned_and_claimed_once() {
+        static STORAGE: RegionStorage<100, 64> = RegionStorage::new();
+        static REGION: HeapRegion<100, 64> = unsafe { HeapRegion::new(&STORAGE) };
+        let bytes = REGION.claim().unwrap();
+        assert_eq!(bytes.addr() % 64, 0);
+        assert_eq!(REGION.size(), 100);
+        assert!(REGION.claim().is_none());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_heap_in_link_section() {
+        heap_region! {
+            #[link_section = ".simple_alloc_test_region"]
+            static REGION: HeapRegion<8192, 4096>;
+        }
+
+        let heap = Heap::empty();
+        heap.init_region(&REGION);
+        assert_eq!(heap.largest_allocatable(4096), 8192);
+
+        let layout = Layout::from_size_align(4096, 4096).unwrap();
+        unsafe {
+            let ptr = heap.alloc(layout);
+            assert!(!ptr.is_null());
+            ptr.write_bytes(0xAB, 4096);
+            assert_eq!(*ptr.add(4095), 0xAB);
+        }
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_init_region_on_initialized_heap_keeps_region() {
+        heap_region! {
+            #[link_section = ".simple_alloc_test_region"]
+            static REGION: HeapRegion<256, 16>;
+        }
+
+        let heap = Heap::empty();
+        let mut other = [0u8; 64];
+        unsafe { heap.init(other.as_mut_ptr().expose_provenance(), other.len()) };
+
+        let result = std::panic::catch_unwind(|| heap.init_region(&REGION));
+        assert!(result.is_err());
+        assert_eq!(heap.largest_allocatable(1), 64);
+        // the region wasn't claimed, so another heap can still have it
+        let second = Heap::empty();
+        second.init_region(&REGION);
+        assert_eq!(second.largest_allocatable(16), 256);
+    }
+}
@@ -0,0 +1,193 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::cell::UnsafeCell;
+use core::ptr;
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::zero_size;
+
+/// Wraps an allocator with a small emergency reserve of `SIZE` bytes, left
+/// untouched until the inner allocator fails inside an
+/// [`emergency`](Self::emergency) scope.
+///
+/// This lets OOM handlers and panic paths allocate, e.g. to format an error
+/// message, when the heap is exhausted. Reserve blocks are bumped, and the
+/// reserve is refilled once they have all been freed. The emergency scope is
+/// shared by all threads.
+///
+/// `SIZE` must be below `2^(usize::BITS / 2)` bytes, so under 64 KiB on
+/// 32-bit targets: the bump offset has to fit in half a word even when the
+/// reserve is full.
+#[derive(Debug)]
+pub struct Reserve<A, const SIZE: usize> {
+    inner: A,
+    memory: UnsafeCell<ReserveMemory<SIZE>>,
+    // the bump offset in the high half, the number of live blocks in the
+    // low half, so that the last free can reset the offset atomically
+    state: AtomicUsize,
+    emergencies: AtomicUsize,
+}
+
+#[derive(Debug)]
+#[repr(align(16))]
+struct ReserveMemory<const SIZE: usize>([u8; SIZE]);
+
+unsafe impl<A: Sync, const SIZE: usize> Sync for Reserve<A, SIZE> {}
+
+const HALF: u32 = usize::BITS / 2;
+const LIVE_MASK: usize = (1 << HALF) - 1;
+
+impl<A, const SIZE: usize> Reserve<A, SIZE> {
+    pub const fn new(inner: A) -> Self {
+        assert!(SIZE < 1 << HALF, "emergency reserve too large");
+        Self {
+            inner,
+            memory: UnsafeCell::new(ReserveMemory([0; SIZE])),
+            state: AtomicUsize::new(0),
+            emergencies: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Runs `f` with the reserve available to allocations the inner
+    /// allocator can't serve.
+    pub fn emergency<R>(&self, f: impl FnOnce() -> R) -> R {
+        struct Leave<'a>(&'a AtomicUsize);
+
+        impl Drop for Leave<'_> {
+            fn drop(&mut self) {
+                self.0.fetch_sub(1, Ordering::Release);
+            }
+        }
+
+        self.emergencies.fetch_add(1, Ordering::Acquire);
+        let _leave = Leave(&self.emergencies);
+        f()
+    }
+
+    /// Bytes left in the reserve.
+    pub fn available(&self) -> usize {
+        SIZE - (self.state.load(Ordering::Relaxed) >> HALF)
+    }
+
+    fn reserve_start(&self) -> *mut u8 {
+        self.memory.get().cast()
+    }
+
+    fn in_reserve(&self, ptr: *mut u8) -> bool {
+        let start = self.reserve_start().addr();
+        (start..start + SIZE).contains(&ptr.addr())
+    }
+
+    fn alloc_reserve(&self, layout: Layout) -> *mut u8 {
+        let start = self.reserve_start();
+        let mut block_offset = 0;
+        let result = self
+            .state
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |state| {
+                let (offset, live) = (state >> HALF, state & LIVE_MASK);
+                let block = (start.addr() + offset).next_multiple_of(layout.align()) - start.addr();
+                let end = block.checked_add(layout.size())?;
+                if end > SIZE || live == LIVE_MASK {
+                    return None;
+                }
+                block_offset = block;
+                Some(end << HALF | (live + 1))
+            });
+        match result {
+            Ok(_) => unsafe { start.add(block_offset) },
+            Err(_) => ptr::null_mut(),
+        }
+    }
+
+    fn dealloc_reserve(&self) {
+        let _ = self
+            .state
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |state| {
+                let live = (state & LIVE_MASK) - 1;
+                // the last block out rewinds the reserve
+                Some(if live == 0 { 0 } else { state - 1 })
+            });
+    }
+}
+
+unsafe impl<A: GlobalAlloc, const SIZE: usize> GlobalAlloc for Reserve<A, SIZE> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == 0 {
+            return zero_size::dangling(layout);
+        }
+        let ptr = unsafe { self.inner.alloc(layout) };
+        if ptr.is_null() && self.emergencies.load(Ordering::Acquire) > 0 {
+            return self.alloc_reserve(layout);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if zero_size::is_dangling(ptr, layout) {
+            return;
+        }
+        if self.in_reserve(ptr) {
+            self.dealloc_reserve();
+        } else {
+            unsafe { self.inner.dealloc(ptr, layout) }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::BumpAllocator;
+
+    test_suite! {
+        Reserve::<_, 64>::new(BumpAllocator::new([0; 65536])),
+        Reserve::<_, 64>::new(BumpAllocator::new([0; 256]))
+    }
+
+    #[test]
+    fn test_reserve_only_used_in_emergencies() {
+        let allocator = Reserve::<_, 512>::new(BumpAllocator::new([0; 256]));
+        let layout = Layout::from_size_align(300, 8).unwrap();
+
+        unsafe {
+            assert!(allocator.alloc(layout).is_null());
+            assert_eq!(allocator.available(), 512);
+
+            let (a, b) = allocator.emergency(|| (allocator.alloc(layout), allocator.alloc(layout)));
+            assert!(allocator.in_reserve(a));
+            assert!(b.is_null(), "the reserve is exhausted");
+            assert_eq!(allocator.available(), 212);
+
+            // freeing the last reserve block refills it
+            allocator.dealloc(a, layout);
+            assert_eq!(allocator.available(), 512);
+            let again = allocator.emergency(|| allocator.alloc(layout));
+            assert_eq!(again, a);
+        }
+    }
+
+    #[test]
+    fn test_full_reserve_stays_full() {
+        let allocator = Reserve::<_, 512>::new(BumpAllocator::new([0; 256]));
+        let all = Layout::from_size_align(512, 16).unwrap();
+        let byte = Layout::new::<u8>();
+
+        unsafe {
+            // use up the inner heap, so every allocation below needs the
+            // reserve
+            let inner = Layout::from_size_align(256, 1).unwrap();
+            assert!(!allocator.alloc(inner).is_null());
+
+            let (a, b) = allocator.emergency(|| (allocator.alloc(all), allocator.alloc(byte)));
+            assert!(allocator.in_reserve(a));
+            assert_eq!(allocator.available(), 0);
+            assert!(b.is_null(), "allocated past the end of a full reserve");
+
+            allocator.dealloc(a, all);
+            assert_eq!(allocator.available(), 512);
+        }
+    }
+}
@@ -0,0 +1,63 @@
+use core::alloc::{GlobalAlloc, Layout};
+
+use crate::zero_size;
+
+/// An upstream allocator that can be picked at runtime, like a C++
+/// `std::pmr::memory_resource`.
+///
+/// The trait is dyn-safe, so allocators parameterized over their upstream,
+/// such as [`Arena`](crate::Arena), accept a `&dyn MemoryResource` as well as
+/// a concrete allocator. Every `GlobalAlloc` is a memory resource.
+pub trait MemoryResource {
+    /// Allocates a block for `layout`, or returns null if it can't. Unlike
+    /// [`GlobalAlloc::alloc`], any layout is allowed: a zero-size one gets a
+    /// [`zero_size::dangling`] pointer.
+    fn allocate(&self, layout: Layout) -> *mut u8;
+
+    /// Frees a block returned by [`allocate`](Self::allocate).
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must have been returned by `allocate` on this resource with the
+    /// same `layout`, and not freed since.
+    unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout);
+}
+
+impl<A: GlobalAlloc> MemoryResource for A {
+    fn allocate(&self, layout: Layout) -> *mut u8 {
+        // a zero-size request would be undefined behavior for the allocator
+        if layout.size() == 0 {
+            return zero_size::dangling(layout);
+        }
+        unsafe { self.alloc(layout) }
+    }
+
+    unsafe fn deallocate(&self, ptr: *mut u8, layout: Layout) {
+        if zero_size::is_dangling(ptr, layout) {
+            return;
+        }
+        unsafe { self.dealloc(ptr, layout) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    extern crate std;
+    use std::alloc::System;
+
+    #[test]
+    fn test_zero_size_never_reaches_the_allocator() {
+        for align in [1, 8, 4096] {
+            let layout = Layout::from_size_align(0, align).unwrap();
+            let ptr = System.allocate(layout);
+            assert_eq!(ptr, zero_size::dangling(layout));
+            unsafe { System.deallocate(ptr, layout) };
+        }
+
+        let layout = Layout::new::<u64>();
+        let ptr = System.allocate(layout);
+        assert!(!ptr.is_null());
+        unsafe { System.deallocate(ptr, layout) };
+    }
+}
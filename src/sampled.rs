@@ -0,0 +1,249 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use core::panic::Location;
+use core::ptr;
+use core::sync::atomic::{AtomicPtr, AtomicU64, AtomicUsize, Ordering};
+
+/// Wraps an allocator and samples one allocated byte in every `rate`, for a
+/// profile of where memory is allocated that is cheap enough to leave on in
+/// production.
+///
+/// An allocation is sampled when it covers a multiple of `rate` in the
+/// running total of bytes allocated, so large allocations are sampled in
+/// proportion to their size, and most small ones cost a single atomic add.
+/// The last `SAMPLES` samples are kept, with the call site for allocations
+/// made through [`alloc_tracked`](Self::alloc_tracked) or the
+/// [`alloc_tracked!`](crate::alloc_tracked) macro.
+///
+/// This profiles allocation volume, not live memory: frees are not
+/// tracked.
+#[derive(Debug)]
+pub struct Sampled<A, const SAMPLES: usize> {
+    inner: A,
+    rate: u64,
+    allocated: AtomicU64,
+    recorded: AtomicUsize,
+    samples: [Slot; SAMPLES],
+}
+
+#[derive(Debug)]
+struct Slot {
+    size: AtomicUsize,
+    weight: AtomicU64,
+    site: AtomicPtr<Location<'static>>,
+}
+
+/// A sampled allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Sample {
+    pub size: usize,
+    /// Bytes of allocation volume this sample stands for: `rate` times the
+    /// number of sampled bytes the allocation covered.
+    pub weight: u64,
+    /// `None` for allocations through plain `GlobalAlloc::alloc`.
+    pub site: Option<&'static Location<'static>>,
+}
+
+impl<A, const SAMPLES: usize> Sampled<A, SAMPLES> {
+    /// Samples one byte in every `rate`.
+    pub const fn new(inner: A, rate: u64) -> Self {
+        assert!(rate > 0, "sampling rate must be positive");
+        assert!(SAMPLES > 0, "at least one sample slot is needed");
+        Self {
+            inner,
+            rate,
+            allocated: AtomicU64::new(0),
+            recorded: AtomicUsize::new(0),
+            samples: [const {
+                Slot {
+                    size: AtomicUsize::new(0),
+                    weight: AtomicU64::new(0),
+                    site: AtomicPtr::new(ptr::null_mut()),
+                }
+            }; SAMPLES],
+        }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Total bytes allocated so far, sampled or not.
+    pub fn allocated(&self) -> u64 {
+        self.allocated.load(Ordering::Relaxed)
+    }
+
+    /// The samples kept, oldest first. A sample being recorded concurrently
+    /// may show up with fields from the one it replaces.
+    pub fn samples(&self) -> impl Iterator<Item = Sample> + '_ {
+        let recorded = self.recorded.load(Ordering::Relaxed);
+        let first = recorded.saturating_sub(SAMPLES);
+        (first..recorded).map(|i| {
+            let slot = &self.samples[i % SAMPLES];
+            Sample {
+                size: slot.size.load(Ordering::Relaxed),
+                weight: slot.weight.load(Ordering::Relaxed),
+                site: unsafe { slot.site.load(Ordering::Relaxed).as_ref() },
+            }
+        })
+    }
+
+    /// Writes the samples kept in folded-stack format, one `stack weight`
+    /// line per call site, for flamegraph tools such as `flamegraph.pl` or
+    /// `inferno-flamegraph`. Each stack is a single `file:line:column`
+    /// frame, or `[untracked]`, weighted by the bytes its samples stand for.
+    pub fn write_folded(&self, out: &mut impl fmt::Write) -> fmt::Result {
+        // sum the weights per site, in order of first appearance, from a
+        // single read of the ring, so that concurrent sampling can't split
+        // or skew a site's line
+        let mut sites = [(None, 0); SAMPLES];
+        let mut len = 0;
+        for sample in self.samples() {
+            match sites[..len]
+                .iter_mut()
+                .find(|(site, _)| *site == sample.site)
+            {
+                Some((_, weight)) => *weight += sample.weight,
+                None => {
+                    sites[len] = (sample.site, sample.weight);
+                    len += 1;
+                }
+            }
+        }
+        for &(site, weight) in &sites[..len] {
+            match site {
+                Some(site) => writeln!(out, "{site} {weight}")?,
+                None => writeln!(out, "[untracked] {weight}")?,
+            }
+        }
+        Ok(())
+    }
+
+    fn sample(&self, size: usize, site: Option<&'static Location<'static>>) {
+        let start = self.allocated.fetch_add(size as u64, Ordering::Relaxed);
+        let points = (start + size as u64) / self.rate - start / self.rate;
+        if points == 0 {
+            return;
+        }
+        let index = self.recorded.fetch_add(1, Ordering::Relaxed);
+        let slot = &self.samples[index % SAMPLES];
+        slot.size.store(size, Ordering::Relaxed);
+        slot.weight.store(points * self.rate, Ordering::Relaxed);
+        slot.site.store(
+            site.map_or(ptr::null_mut(), |site| ptr::from_ref(site).cast_mut()),
+            Ordering::Relaxed,
+        );
+    }
+}
+
+impl<A: GlobalAlloc, const SAMPLES: usize> Sampled<A, SAMPLES> {
+    /// Allocates `layout`, with the caller's location recorded if the
+    /// allocation is sampled. Free the block with `dealloc` as usual.
+    ///
+    /// # Safety
+    ///
+    /// Same contract as [`GlobalAlloc::alloc`].
+    #[track_caller]
+    pub unsafe fn alloc_tracked(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+        if !ptr.is_null() {
+            self.sample(layout.size(), Some(Location::caller()));
+        }
+        ptr
+    }
+}
+
+unsafe impl<A: GlobalAlloc, const SAMPLES: usize> GlobalAlloc for Sampled<A, SAMPLES> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+        if !ptr.is_null() {
+            self.sample(layout.size(), None);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.inner.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { self.inner.realloc(ptr, layout, new_size) };
+        if !new_ptr.is_null() && new_size > layout.size() {
+            self.sample(new_size - layout.size(), None);
+        }
+        new_ptr
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::BumpAllocator;
+
+    test_suite! {
+        Sampled::<_, 8>::new(BumpAllocator::new([0; 65536]), 1024),
+        Sampled::<_, 8>::new(BumpAllocator::new([0; 256]), 1024)
+    }
+
+    #[test]
+    fn test_samples_every_nth_byte() {
+        let allocator = Sampled::<_, 4>::new(BumpAllocator::new([0; 8192]), 1000);
+
+        unsafe {
+            // 600 bytes in 100-byte blocks: none covers byte 1000
+            for _ in 0..6 {
+                allocator.alloc(Layout::from_size_align(100, 1).unwrap());
+            }
+            assert_eq!(allocator.samples().count(), 0);
+
+            // covers bytes 1000 and 2000
+            let layout = Layout::from_size_align(1500, 1).unwrap();
+            let site = Location::caller();
+            crate::alloc_tracked!(allocator, layout);
+            assert_eq!(allocator.allocated(), 2100);
+
+            let sample = allocator.samples().next().unwrap();
+            assert_eq!((sample.size, sample.weight), (1500, 2000));
+            let sampled_site = sample.site.unwrap();
+            assert_eq!(sampled_site.file(), site.file());
+            assert_eq!(sampled_site.line(), site.line() + 1);
+
+            // only the last 4 samples are kept
+            for _ in 0..5 {
+                allocator.alloc(Layout::from_size_align(1000, 1).unwrap());
+            }
+            let samples = std::vec::Vec::from_iter(allocator.samples());
+            assert_eq!(samples.len(), 4);
+            assert!(
+                samples
+                    .iter()
+                    .all(|sample| sample.size == 1000 && sample.site.is_none())
+            );
+        }
+    }
+
+    #[test]
+    fn test_write_folded() {
+        let allocator = Sampled::<_, 8>::new(BumpAllocator::new([0; 8192]), 100);
+        let layout = Layout::from_size_align(100, 1).unwrap();
+
+        let tracked =
+            |allocator: &Sampled<_, 8>| unsafe { crate::alloc_tracked!(allocator, layout) };
+        unsafe {
+            for _ in 0..3 {
+                tracked(&allocator);
+                allocator.alloc(layout);
+            }
+            crate::alloc_tracked!(allocator, layout);
+        }
+
+        let mut out = std::string::String::new();
+        allocator.write_folded(&mut out).unwrap();
+        let lines = std::vec::Vec::from_iter(out.lines());
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with(file!()) && lines[0].ends_with(" 300"));
+        assert_eq!(lines[1], "[untracked] 300");
+        assert!(lines[2].starts_with(file!()) && lines[2].ends_with(" 100"));
+        assert_ne!(lines[0].split(' ').next(), lines[2].split(' ').next());
+    }
+}
@@ -0,0 +1,630 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::fmt;
+use core::ptr;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::ffi::CStr;
+use std::io;
+
+use crate::{AllocError, zero_size};
+
+/// A heap living in a POSIX shared memory segment.
+///
+/// All internal links are offsets from the start of the segment, so every
+/// process that maps the segment can allocate and free from it, wherever the
+/// mapping ends up in its address space. Pointers returned by `alloc` are
+/// only meaningful in the process that received them; use [`offset_of`] and
+/// [`ptr_at`] to pass blocks between processes.
+///
+/// Free blocks are kept in an address-ordered list and coalesced with their
+/// neighbours on `dealloc`. A spinlock in the segment header serializes all
+/// processes.
+///
+/// [`offset_of`]: SharedMemAllocator::offset_of
+/// [`ptr_at`]: SharedMemAllocator::ptr_at
+#[derive(Debug)]
+pub struct SharedMemAllocator {
+    base: *mut u8,
+    size: usize,
+}
+
+unsafe impl Send for SharedMemAllocator {}
+unsafe impl Sync for SharedMemAllocator {}
+
+const MAGIC: u64 = u64::from_le_bytes(*b"shmheap1");
+const NONE: u64 = 0;
+const BLOCK_ALIGN: usize = 16;
+const MIN_BLOCK: usize = 32;
+
+#[repr(C)]
+struct SegmentHeader {
+    magic: AtomicU64,
+    size: u64,
+    lock: AtomicU32,
+    free_head: u64,
+}
+
+const HEAP_START: usize = size_of::<SegmentHeader>().next_multiple_of(BLOCK_ALIGN);
+
+// Every block starts with this header. While a block is allocated, `next` is
+// unused and the word right before the user pointer holds the block's offset.
+#[repr(C)]
+struct BlockHeader {
+    size: u64,
+    next: u64,
+}
+
+impl SharedMemAllocator {
+    /// Creates a new segment named `name` (e.g. `c"/my-heap"`) of `size`
+    /// bytes, failing if it already exists.
+    pub fn create(name: &CStr, size: usize) -> io::Result<Self> {
+        if size < HEAP_START + MIN_BLOCK {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "shared memory segment too small",
+            ));
+        }
+        let fd = unsafe {
+            libc::shm_open(
+                name.as_ptr(),
+                libc::O_CREAT | libc::O_EXCL | libc::O_RDWR,
+                0o600,
+            )
+        };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        if unsafe { libc::ftruncate(fd, size as libc::off_t) } != 0 {
+            let err = io::Error::last_os_error();
+            unsafe {
+                libc::close(fd);
+                libc::shm_unlink(name.as_ptr());
+            }
+            return Err(err);
+        }
+        let allocator = Self::map(fd, size).inspect_err(|_| unsafe {
+            libc::shm_unlink(name.as_ptr());
+        })?;
+
+        let heap_size = (size - HEAP_START) / BLOCK_ALIGN * BLOCK_ALIGN;
+        unsafe {
+            let first = allocator.block(HEAP_START as u64);
+            (*first).size = heap_size as u64;
+            (*first).next = NONE;
+            let header = allocator.header();
+            (*header).size = size as u64;
+            (*header).free_head = HEAP_START as u64;
+            (*header).magic.store(MAGIC, Ordering::Release);
+        }
+        Ok(allocator)
+    }
+
+    /// Maps an existing segment created by [`create`](Self::create),
+    /// possibly from another process.
+    pub fn open(name: &CStr) -> io::Result<Self> {
+        let fd = unsafe { libc::shm_open(name.as_ptr(), libc::O_RDWR, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let mut stat = unsafe { core::mem::zeroed::<libc::stat>() };
+        if unsafe { libc::fstat(fd, &mut stat) } != 0 {
+            let err = io::Error::last_os_error();
+            unsafe { libc::close(fd) };
+            return Err(err);
+        }
+        let size = stat.st_size as usize;
+        if size < HEAP_START + MIN_BLOCK {
+            unsafe { libc::close(fd) };
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a shared memory heap",
+            ));
+        }
+        let allocator = Self::map(fd, size)?;
+        let header = allocator.header();
+        unsafe {
+            if (*header).magic.load(Ordering::Acquire) != MAGIC || (*header).size != size as u64 {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "not a shared memory heap",
+                ));
+            }
+        }
+        Ok(allocator)
+    }
+
+    /// Removes the segment name. Existing mappings stay valid until dropped.
+    pub fn unlink(name: &CStr) -> io::Result<()> {
+        if unsafe { libc::shm_unlink(name.as_ptr()) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+
+    fn map(fd: libc::c_int, size: usize) -> io::Result<Self> {
+        let base = unsafe {
+            libc::mmap(
+                ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd,
+                0,
+            )
+        };
+        // the mapping keeps the segment alive on its own
+        unsafe { libc::close(fd) };
+        if base == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Self {
+            base: base.cast(),
+            size,
+        })
+    }
+
+    /// Total size of the segment, including its header.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Offset of `ptr` from the start of the segment, valid in every process
+    /// mapping it.
+    pub fn offset_of(&self, ptr: *const u8) -> usize {
+        debug_assert!((self.base.addr()..self.base.addr() + self.size).contains(&ptr.addr()));
+        ptr.addr() - self.base.addr()
+    }
+
+    /// Pointer to `offset` within this process's mapping of the segment.
+    pub fn ptr_at(&self, offset: usize) -> *mut u8 {
+        debug_assert!(offset < self.size);
+        unsafe { self.base.add(offset) }
+    }
+
+    /// Writes the segment's state as a JSON object, for bug reports: its
+    /// size and the free list, in address order. The segment lock is held
+    /// while writing, so `out` must not allocate from this segment.
+    pub fn dump_state(&self, out: &mut impl fmt::Write) -> fmt::Result {
+        let _guard = self.lock();
+        write!(
+            out,
+            r#"{{"allocator":"shared_mem","size":{},"free_blocks":["#,
+            self.size
+        )?;
+        let mut offset = unsafe { (*self.header()).free_head };
+        let mut separator = "";
+        while offset != NONE {
+            let block = self.block(offset);
+            out.write_str(separator)?;
+            separator = ",";
+            write!(
+                out,
+                r#"{{"offset":{offset},"size":{}}}"#,
+                unsafe { (*block).size }
+            )?;
+            offset = unsafe { (*block).next };
+        }
+        out.write_str("]}")
+    }
+
+    /// How much of the heap part of the segment is allocated, in
+    /// thousandths, counting block headers as allocated.
+    pub fn occupancy_permille(&self) -> u32 {
+        let heap_size = (self.size - HEAP_START) / BLOCK_ALIGN * BLOCK_ALIGN;
+        let mut free = 0;
+        self.for_each_free(|_, size| free += size);
+        ((heap_size - free) as u64 * 1000 / heap_size as u64) as u32
+    }
+
+    /// Size of the largest block with alignment `align` that an allocation
+    /// would succeed for right now. Other processes may allocate in the
+    /// meantime.
+    ///
+    /// # Panics
+    ///
+    /// If `align` is not a power of two.
+    pub fn largest_allocatable(&self, align: usize) -> usize {
+        assert!(align.is_power_of_two(), "alignment must be a power of two");
+        let mut largest = 0;
+        self.for_each_free(|offset, size| {
+            let user = (self.base.addr() + offset + size_of::<BlockHeader>())
+                .next_multiple_of(align)
+                - self.base.addr();
+            largest = largest.max((offset + size).saturating_sub(user));
+        });
+        largest
+    }
+
+    /// Calls `f` with the offset and size of every free block, under the
+    /// segment lock.
+    fn for_each_free(&self, mut f: impl FnMut(usize, usize)) {
+        let _guard = self.lock();
+        let mut offset = unsafe { (*self.header()).free_head };
+        while offset != NONE {
+            let block = self.block(offset);
+            f(offset as usize, unsafe { (*block).size } as usize);
+            offset = unsafe { (*block).next };
+        }
+    }
+
+    /// Allocates `layout` at `offset` from the start of the segment, for
+    /// structures that every process expects at a fixed place.
+    ///
+    /// Fails if the range, with the block header in front of it, is not
+    /// entirely free, if `offset` is not a multiple of 16 or the resulting
+    /// address is not aligned to `layout`, or if carving the range out would
+    /// leave a free fragment too small to track in front of it. The block is
+    /// freed with `dealloc` like any other.
+    pub fn allocate_at(&self, offset: usize, layout: Layout) -> Result<*mut u8, AllocError> {
+        if layout.size() == 0 {
+            return Ok(zero_size::dangling(layout));
+        }
+        if !offset.is_multiple_of(BLOCK_ALIGN)
+            || offset < HEAP_START + size_of::<BlockHeader>()
+            || !(self.base.addr() + offset).is_multiple_of(layout.align())
+        {
+            return Err(AllocError);
+        }
+        let start = offset - size_of::<BlockHeader>();
+        let end = offset
+            .checked_add(layout.size())
+            .and_then(|end| end.checked_next_multiple_of(BLOCK_ALIGN))
+            .ok_or(AllocError)?;
+
+        let _guard = self.lock();
+        let header = self.header();
+        let mut prev: *mut u64 = unsafe { &raw mut (*header).free_head };
+        let mut free_offset = unsafe { *prev };
+        while free_offset != NONE && free_offset as usize <= start {
+            let block = self.block(free_offset);
+            let block_start = free_offset as usize;
+            let block_end = block_start + unsafe { (*block).size } as usize;
+            if end <= block_end {
+                let front = start - block_start;
+                if front != 0 && front < MIN_BLOCK {
+                    return Err(AllocError);
+                }
+                let mut used = end - start;
+                unsafe {
+                    let mut next = (*block).next;
+                    if block_end - end >= MIN_BLOCK {
+                        let rest = end as u64;
+                        let rest_block = self.block(rest);
+                        (*rest_block).size = (block_end - end) as u64;
+                        (*rest_block).next = next;
+                        next = rest;
+                    } else {
+                        used = block_end - start;
+                    }
+                    if front == 0 {
+                        *prev = next;
+                    } else {
+                        (*block).size = front as u64;
+                        (*block).next = next;
+                    }
+                    let allocated = self.block(start as u64);
+                    (*allocated).size = used as u64;
+                    let user_ptr = self.ptr_at(offset);
+                    user_ptr.cast::<u64>().sub(1).write(start as u64);
+                    return Ok(user_ptr);
+                }
+            }
+            prev = unsafe { &raw mut (*block).next };
+            free_offset = unsafe { *prev };
+        }
+        Err(AllocError)
+    }
+
+    fn header(&self) -> *mut SegmentHeader {
+        self.base.cast()
+    }
+
+    fn block(&self, offset: u64) -> *mut BlockHeader {
+        self.ptr_at(offset as usize).cast()
+    }
+
+    fn lock(&self) -> SegmentGuard<'_> {
+        let lock = unsafe { &(*self.header()).lock };
+        while lock
+            .compare_exchange_weak(0, 1, Ordering::Acquire, Ordering::Relaxed)
+            .is_err()
+        {
+            core::hint::spin_loop();
+        }
+        SegmentGuard { lock }
+    }
+
+    /// Carves `layout` out of the first free block that can hold it.
+    ///
+    /// Must be called with the segment lock held.
+    unsafe fn alloc_locked(&self, layout: Layout) -> *mut u8 {
+        let header = self.header();
+        let mut prev: *mut u64 = unsafe { &raw mut (*header).free_head };
+        let mut offset = unsafe { *prev };
+        while offset != NONE {
+            let block = self.block(offset);
+            let block_start = offset as usize;
+            let block_size = unsafe { (*block).size } as usize;
+            // align the address rather than the offset, so alignments above
+            // the page size still hold in this process
+            let user = (self.base.addr() + block_start + size_of::<BlockHeader>())
+                .next_multiple_of(layout.align())
+                - self.base.addr();
+            let used = (user + layout.size()).next_multiple_of(BLOCK_ALIGN) - block_start;
+            if used <= block_size {
+                let next = unsafe { (*block).next };
+                unsafe {
+                    if block_size - used >= MIN_BLOCK {
+                        let rest = offset + used as u64;
+                        let rest_block = self.block(rest);
+                        (*rest_block).size = (block_size - used) as u64;
+                        (*rest_block).next = next;
+                        (*block).size = used as u64;
+                        *prev = rest;
+                    } else {
+                        *prev = next;
+                    }
+                    let user_ptr = self.ptr_at(user);
+                    user_ptr.cast::<u64>().sub(1).write(offset);
+                    return user_ptr;
+                }
+            }
+            prev = unsafe { &raw mut (*block).next };
+            offset = unsafe { *prev };
+        }
+        ptr::null_mut()
+    }
+
+    /// Returns the block owning `ptr` to the free list, merging it with free
+    /// neighbours.
+    ///
+    /// Must be called with the segment lock held.
+    unsafe fn dealloc_locked(&self, ptr: *mut u8) {
+        let offset = unsafe { ptr.cast::<u64>().sub(1).read() };
+        let block = self.block(offset);
+        let header = self.header();
+
+        // find the free blocks on either side, keeping the list address-ordered
+        let mut prev_offset = NONE;
+        let mut next_offset = unsafe { (*header).free_head };
+        while next_offset != NONE && next_offset < offset {
+            prev_offset = next_offset;
+            next_offset = unsafe { (*self.block(next_offset)).next };
+        }
+
+        unsafe {
+            (*block).next = next_offset;
+            if next_offset != NONE && offset + (*block).size == next_offset {
+                let next = self.block(next_offset);
+                (*block).size += (*next).size;
+                (*block).next = (*next).next;
+            }
+
+            if prev_offset == NONE {
+                (*header).free_head = offset;
+            } else {
+                let prev = self.block(prev_offset);
+                if prev_offset + (*prev).size == offset {
+                    (*prev).size += (*block).size;
+                    (*prev).next = (*block).next;
+                } else {
+                    (*prev).next = offset;
+                }
+            }
+        }
+    }
+}
+
+impl Drop for SharedMemAllocator {
+    fn drop(&mut self) {
+        unsafe { libc::munmap(self.base.cast(), self.size) };
+    }
+}
+
+struct SegmentGuard<'a> {
+    lock: &'a AtomicU32,
+}
+
+impl Drop for SegmentGuard<'_> {
+    fn drop(&mut self) {
+        self.lock.store(0, Ordering::Release);
+    }
+}
+
+unsafe impl GlobalAlloc for SharedMemAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == 0 {
+            return zero_size::dangling(layout);
+        }
+        let _guard = self.lock();
+        unsafe { self.alloc_locked(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if zero_size::is_dangling(ptr, layout) {
+            return;
+        }
+        let _guard = self.lock();
+        unsafe { self.dealloc_locked(ptr) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::ffi::CString;
+
+    fn segment_name() -> CString {
+        static NEXT: AtomicU32 = AtomicU32::new(0);
+        let id = NEXT.fetch_add(1, Ordering::Relaxed);
+        CString::new(std::format!(
+            "/simple-alloc-test-{}-{id}",
+            std::process::id()
+        ))
+        .unwrap()
+    }
+
+    // The name is unlinked right away; the mapping keeps the segment alive.
+    fn anonymous(size: usize) -> SharedMemAllocator {
+        let name = segment_name();
+        let allocator = SharedMemAllocator::create(&name, size).unwrap();
+        SharedMemAllocator::unlink(&name).unwrap();
+        allocator
+    }
+
+    test_suite! {
+        anonymous(65536),
+        anonymous(256)
+    }
+
+    #[test]
+    fn test_shared_between_mappings() {
+        let name = segment_name();
+        let a = SharedMemAllocator::create(&name, 4096).unwrap();
+        let b = SharedMemAllocator::open(&name).unwrap();
+        SharedMemAllocator::unlink(&name).unwrap();
+        assert_ne!(a.ptr_at(0), b.ptr_at(0), "expected two distinct mappings");
+
+        unsafe {
+            let layout = Layout::from_size_align(64, 8).unwrap();
+            let ptr = a.alloc(layout);
+            assert!(!ptr.is_null());
+            ptr.write_bytes(0xAB, 64);
+
+            // the other mapping sees the same block at the same offset
+            let offset = a.offset_of(ptr);
+            let alias = b.ptr_at(offset);
+            assert!(
+                std::slice::from_raw_parts(alias, 64)
+                    .iter()
+                    .all(|&x| x == 0xAB)
+            );
+
+            // free through the other mapping, then reuse through the first
+            b.dealloc(alias, layout);
+            let again = a.alloc(layout);
+            assert_eq!(a.offset_of(again), offset);
+            a.dealloc(again, layout);
+
+            // everything coalesced back into a single block
+            let big = Layout::from_size_align(4096 - HEAP_START - 16, 8).unwrap();
+            let ptr = b.alloc(big);
+            assert!(!ptr.is_null());
+            b.dealloc(ptr, big);
+        }
+    }
+
+    #[test]
+    fn test_dump_state() {
+        let allocator = anonymous(4096);
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        unsafe {
+            let a = allocator.alloc(layout);
+            allocator.alloc(layout);
+            allocator.dealloc(a, layout);
+        }
+
+        let mut out = std::string::String::new();
+        allocator.dump_state(&mut out).unwrap();
+        assert_eq!(
+            out,
+            std::format!(
+                r#"{{"allocator":"shared_mem","size":4096,"free_blocks":[{{"offset":{HEAP_START},"size":80}},{{"offset":{},"size":{}}}]}}"#,
+                HEAP_START + 160,
+                4096 - HEAP_START - 160
+            )
+        );
+    }
+
+    #[test]
+    fn test_allocate_at() {
+        let allocator = anonymous(4096);
+        let layout = Layout::from_size_align(64, 16).unwrap();
+
+        let pinned = allocator.allocate_at(1024, layout).unwrap();
+        assert_eq!(allocator.offset_of(pinned), 1024);
+        unsafe { pinned.write_bytes(0xCD, 64) };
+
+        // taken, overlapping, too close to a free block's start, misaligned
+        assert_eq!(allocator.allocate_at(1024, layout), Err(AllocError));
+        assert_eq!(allocator.allocate_at(1056, layout), Err(AllocError));
+        assert_eq!(
+            allocator.allocate_at(HEAP_START + size_of::<BlockHeader>() + 16, layout),
+            Err(AllocError)
+        );
+        assert_eq!(allocator.allocate_at(2056, layout), Err(AllocError));
+
+        // ordinary allocations go around the pinned block
+        unsafe {
+            let small = Layout::from_size_align(512, 8).unwrap();
+            let mut blocks = std::vec::Vec::new();
+            loop {
+                let ptr = allocator.alloc(small);
+                if ptr.is_null() {
+                    break;
+                }
+                let offset = allocator.offset_of(ptr);
+                assert!(offset + 512 <= 1024 - 16 || offset >= 1024 + 64);
+                blocks.push(ptr);
+            }
+            assert!(
+                std::slice::from_raw_parts(pinned, 64)
+                    .iter()
+                    .all(|&x| x == 0xCD)
+            );
+            for ptr in blocks {
+                allocator.dealloc(ptr, small);
+            }
+            allocator.dealloc(pinned, layout);
+        }
+
+        // freeing it coalesced everything back
+        let mut out = std::string::String::new();
+        allocator.dump_state(&mut out).unwrap();
+        assert_eq!(
+            out,
+            std::format!(
+                r#"{{"allocator":"shared_mem","size":4096,"free_blocks":[{{"offset":{HEAP_START},"size":{}}}]}}"#,
+                4096 - HEAP_START
+            )
+        );
+    }
+
+    #[test]
+    fn test_occupancy_and_largest_allocatable() {
+        let allocator = anonymous(4096);
+        let heap_size = 4096 - HEAP_START;
+        assert_eq!(allocator.occupancy_permille(), 0);
+        assert_eq!(allocator.largest_allocatable(16), heap_size - 16);
+
+        // split the heap in two free blocks around a pinned one
+        let layout = Layout::from_size_align(64, 16).unwrap();
+        let pinned = allocator.allocate_at(1024, layout).unwrap();
+        assert_eq!(allocator.occupancy_permille(), (80 * 1000 / heap_size) as u32);
+        let largest = allocator.largest_allocatable(16);
+        assert_eq!(largest, 4096 - (1024 + 64) - 16);
+
+        unsafe {
+            let layout = Layout::from_size_align(largest, 16).unwrap();
+            let ptr = allocator.alloc(layout);
+            assert!(!ptr.is_null());
+            assert!(allocator.offset_of(ptr) > 1024);
+            allocator.dealloc(ptr, layout);
+            allocator.dealloc(pinned, Layout::from_size_align(64, 16).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_open_rejects_foreign_segment() {
+        let name = segment_name();
+        let fd = unsafe { libc::shm_open(name.as_ptr(), libc::O_CREAT | libc::O_RDWR, 0o600) };
+        assert!(fd >= 0);
+        unsafe {
+            libc::ftruncate(fd, 4096);
+            libc::close(fd);
+        }
+
+        let err = SharedMemAllocator::open(&name).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        SharedMemAllocator::unlink(&name).unwrap();
+    }
+}
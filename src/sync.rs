@@ -0,0 +1,23 @@
+//! The atomics of the bump allocation path, swapped for loom's under
+//! `--cfg loom` so tests/loom.rs can model-check their interleavings.
+
+#[cfg(not(loom))]
+pub(crate) use core::sync::atomic::AtomicPtr;
+#[cfg(loom)]
+pub(crate) use loom::sync::atomic::AtomicPtr;
+
+/// Declares a `const fn`, except under loom, whose atomics can't be created
+/// in a const context.
+macro_rules! const_fn_unless_loom {
+    ($(#[$attr:meta])* $vis:vis fn $($rest:tt)*) => {
+        #[cfg(not(loom))]
+        $(#[$attr])*
+        $vis const fn $($rest)*
+
+        #[cfg(loom)]
+        $(#[$attr])*
+        $vis fn $($rest)*
+    };
+}
+
+pub(crate) use const_fn_unless_loom;
@@ -0,0 +1,440 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::zero_size;
+
+/// Wraps an allocator and accounts the bytes allocated under each of `TAGS`
+/// tags, to answer questions like "how much RAM does the network stack
+/// use".
+///
+/// Tags are plain indices below `TAGS`, typically a user-defined enum cast
+/// to `usize`. Tag 0 is the default. Allocations are charged to the current
+/// tag, set with [`with_tag`](Self::with_tag), or to an explicit one with
+/// [`alloc_tagged`](Self::alloc_tagged). Every block remembers its tag, so it
+/// is credited back correctly wherever it is freed.
+///
+/// The current tag is shared by all threads: `with_tag` suits single-core
+/// firmware, while multi-threaded code should use `alloc_tagged`.
+///
+/// Each tag can also be given a quota with [`set_quota`](Self::set_quota),
+/// beyond which its allocations fail, to share one heap between subsystems
+/// without letting any of them starve the others. A lower soft limit, set
+/// with [`set_soft_limit`](Self::set_soft_limit), doesn't fail anything but
+/// calls the handler given to
+/// [`with_pressure_handler`](Self::with_pressure_handler) when crossed, so
+/// the subsystem can shed caches or trim pools before the quota starts
+/// failing its allocations. An [`Account`] is a handle that allocates under
+/// a given tag.
+///
+/// The tag is stored in front of each block, which costs at least one word
+/// per allocation. Each tag's counters sit on a cache line of their own, so
+/// threads allocating under different tags don't contend. They are not
+/// sharded per thread: the quota and soft limit are checked against the
+/// exact byte count, which has to live in a single atomic.
+#[derive(Debug)]
+pub struct Tagged<A, const TAGS: usize> {
+    inner: A,
+    current: AtomicUsize,
+    counters: [TagCounters; TAGS],
+    on_pressure: Option<fn(usize, usize)>,
+}
+
+// 128 rather than 64 so the spatial prefetcher doesn't pair up lines of
+// neighbouring tags
+#[derive(Debug)]
+#[repr(align(128))]
+struct TagCounters {
+    bytes: AtomicUsize,
+    allocations: AtomicUsize,
+    quota: AtomicUsize,
+    soft_limit: AtomicUsize,
+}
+
+impl TagCounters {
+    const fn new() -> Self {
+        Self {
+            bytes: AtomicUsize::new(0),
+            allocations: AtomicUsize::new(0),
+            quota: AtomicUsize::new(usize::MAX),
+            soft_limit: AtomicUsize::new(usize::MAX),
+        }
+    }
+}
+
+impl<A, const TAGS: usize> Tagged<A, TAGS> {
+    pub const fn new(inner: A) -> Self {
+        assert!(TAGS > 0, "at least one tag is needed");
+        Self {
+            inner,
+            current: AtomicUsize::new(0),
+            counters: [const { TagCounters::new() }; TAGS],
+            on_pressure: None,
+        }
+    }
+
+    /// Sets the function called with the tag and its usage in bytes when an
+    /// allocation takes a tag over its soft limit. It runs after that
+    /// allocation succeeded, on the allocating thread, and may allocate.
+    pub const fn with_pressure_handler(mut self, on_pressure: fn(usize, usize)) -> Self {
+        self.on_pressure = Some(on_pressure);
+        self
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    /// Runs `f` with allocations charged to `tag`, then restores the
+    /// previous tag.
+    pub fn with_tag<R>(&self, tag: usize, f: impl FnOnce() -> R) -> R {
+        assert!(tag < TAGS, "tag {tag} out of range");
+
+        struct Restore<'a>(&'a AtomicUsize, usize);
+
+        impl Drop for Restore<'_> {
+            fn drop(&mut self) {
+                self.0.store(self.1, Ordering::Relaxed);
+            }
+        }
+
+        let _restore = Restore(&self.current, self.current.swap(tag, Ordering::Relaxed));
+        f()
+    }
+
+    /// Bytes currently allocated under `tag`, excluding the tag headers.
+    pub fn bytes(&self, tag: usize) -> usize {
+        self.counters[tag].bytes.load(Ordering::Relaxed)
+    }
+
+    /// Number of blocks currently allocated under `tag`.
+    pub fn allocations(&self, tag: usize) -> usize {
+        self.counters[tag].allocations.load(Ordering::Relaxed)
+    }
+
+    /// Limits the bytes allocated under `tag`, excluding the tag headers, to
+    /// `quota`. Allocations that would exceed it fail. Lowering the quota
+    /// below the current usage only affects new allocations.
+    pub fn set_quota(&self, tag: usize, quota: usize) {
+        self.counters[tag].quota.store(quota, Ordering::Relaxed);
+    }
+
+    /// The quota of `tag`, `usize::MAX` if it has none.
+    pub fn quota(&self, tag: usize) -> usize {
+        self.counters[tag].quota.load(Ordering::Relaxed)
+    }
+
+    /// Calls the pressure handler whenever an allocation takes the bytes
+    /// allocated under `tag` from `soft_limit` or below to above it.
+    pub fn set_soft_limit(&self, tag: usize, soft_limit: usize) {
+        self.counters[tag]
+            .soft_limit
+            .store(soft_limit, Ordering::Relaxed);
+    }
+
+    /// The soft limit of `tag`, `usize::MAX` if it has none.
+    pub fn soft_limit(&self, tag: usize) -> usize {
+        self.counters[tag].soft_limit.load(Ordering::Relaxed)
+    }
+
+    /// A handle allocating under `tag`.
+    pub fn account(&self, tag: usize) -> Account<'_, A, TAGS> {
+        assert!(tag < TAGS, "tag {tag} out of range");
+        Account {
+            allocator: self,
+            tag,
+        }
+    }
+
+    /// Bytes currently allocated under every tag, indexed by tag.
+    pub fn report(&self) -> [usize; TAGS] {
+        core::array::from_fn(|tag| self.bytes(tag))
+    }
+}
+
+/// The layout of a block with its tag in front, and the offset of the
+/// user's block in it.
+fn with_header(layout: Layout) -> Option<(Layout, usize)> {
+    Layout::new::<usize>().extend(layout).ok()
+}
+
+impl<A: GlobalAlloc, const TAGS: usize> Tagged<A, TAGS> {
+    /// Allocates `layout` charged to `tag`, whatever the current tag. Free
+    /// the block with `dealloc` as usual.
+    ///
+    /// # Safety
+    ///
+    /// Same contract as [`GlobalAlloc::alloc`].
+    pub unsafe fn alloc_tagged(&self, tag: usize, layout: Layout) -> *mut u8 {
+        assert!(tag < TAGS, "tag {tag} out of range");
+        if layout.size() == 0 {
+            return zero_size::dangling(layout);
+        }
+        let Some((outer, offset)) = with_header(layout) else {
+            return core::ptr::null_mut();
+        };
+        // charge the tag first, so concurrent allocations can't overrun it
+        let counters = &self.counters[tag];
+        let quota = counters.quota.load(Ordering::Relaxed);
+        let charged = counters
+            .bytes
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |bytes| {
+                bytes
+                    .checked_add(layout.size())
+                    .filter(|&bytes| bytes <= quota)
+            });
+        let Ok(before) = charged else {
+            return core::ptr::null_mut();
+        };
+        let block = unsafe { self.inner.alloc(outer) };
+        if block.is_null() {
+            counters.bytes.fetch_sub(layout.size(), Ordering::Relaxed);
+            return block;
+        }
+        counters.allocations.fetch_add(1, Ordering::Relaxed);
+        let after = before + layout.size();
+        let soft_limit = counters.soft_limit.load(Ordering::Relaxed);
+        if let Some(on_pressure) = self.on_pressure
+            && before <= soft_limit
+            && after > soft_limit
+        {
+            on_pressure(tag, after);
+        }
+        unsafe {
+            let ptr = block.add(offset);
+            ptr.cast::<usize>().sub(1).write(tag);
+            ptr
+        }
+    }
+}
+
+unsafe impl<A: GlobalAlloc, const TAGS: usize> GlobalAlloc for Tagged<A, TAGS> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        unsafe { self.alloc_tagged(self.current.load(Ordering::Relaxed), layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if zero_size::is_dangling(ptr, layout) {
+            return;
+        }
+        let (outer, offset) = with_header(layout).unwrap();
+        unsafe {
+            let tag = ptr.cast::<usize>().sub(1).read();
+            let counters = &self.counters[tag];
+            counters.bytes.fetch_sub(layout.size(), Ordering::Relaxed);
+            counters.allocations.fetch_sub(1, Ordering::Relaxed);
+            self.inner.dealloc(ptr.sub(offset), outer);
+        }
+    }
+
+    // the default would charge the new block to the current tag
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let tag = if zero_size::is_dangling(ptr, layout) {
+            self.current.load(Ordering::Relaxed)
+        } else {
+            unsafe { ptr.cast::<usize>().sub(1).read() }
+        };
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        let new_ptr = unsafe { self.alloc_tagged(tag, new_layout) };
+        if !new_ptr.is_null() {
+            unsafe {
+                new_ptr.copy_from_nonoverlapping(ptr, layout.size().min(new_size));
+                self.dealloc(ptr, layout);
+            }
+        }
+        new_ptr
+    }
+}
+
+/// Allocates under one tag of a [`Tagged`] allocator, within its quota, so
+/// a subsystem can be handed its own allocator.
+#[derive(Debug)]
+pub struct Account<'a, A, const TAGS: usize> {
+    allocator: &'a Tagged<A, TAGS>,
+    tag: usize,
+}
+
+impl<A, const TAGS: usize> Clone for Account<'_, A, TAGS> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<A, const TAGS: usize> Copy for Account<'_, A, TAGS> {}
+
+impl<A, const TAGS: usize> Account<'_, A, TAGS> {
+    pub fn tag(&self) -> usize {
+        self.tag
+    }
+
+    /// Bytes currently allocated under the account.
+    pub fn used(&self) -> usize {
+        self.allocator.bytes(self.tag)
+    }
+
+    /// Bytes left before the quota is reached.
+    pub fn remaining(&self) -> usize {
+        self.allocator.quota(self.tag).saturating_sub(self.used())
+    }
+}
+
+unsafe impl<A: GlobalAlloc, const TAGS: usize> GlobalAlloc for Account<'_, A, TAGS> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        unsafe { self.allocator.alloc_tagged(self.tag, layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.allocator.dealloc(ptr, layout) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::BumpAllocator;
+
+    test_suite! {
+        Tagged::<_, 2>::new(BumpAllocator::new([0; 65536])),
+        Tagged::<_, 2>::new(BumpAllocator::new([0; 256]))
+    }
+
+    const SYSTEM: usize = 0;
+    const NETWORK: usize = 1;
+    const BLUETOOTH: usize = 2;
+
+    #[test]
+    fn test_bytes_accounted_per_tag() {
+        let allocator = Tagged::<_, 3>::new(BumpAllocator::new([0; 4096]));
+        let layout = Layout::from_size_align(100, 8).unwrap();
+
+        unsafe {
+            let system = allocator.alloc(layout);
+            let network = allocator.with_tag(NETWORK, || {
+                let aligned = allocator.alloc(Layout::from_size_align(64, 64).unwrap());
+                assert_eq!(aligned.addr() % 64, 0);
+                allocator.alloc(layout)
+            });
+            let bluetooth = allocator.alloc_tagged(BLUETOOTH, layout);
+            assert_eq!(allocator.report(), [100, 164, 100]);
+
+            // freed outside with_tag, still credited to the network tag
+            allocator.dealloc(network, layout);
+            allocator.dealloc(bluetooth, layout);
+            allocator.dealloc(system, layout);
+        }
+        assert_eq!(allocator.bytes(SYSTEM), 0);
+        assert_eq!(allocator.bytes(NETWORK), 64);
+        assert_eq!(allocator.allocations(NETWORK), 1);
+        assert_eq!(allocator.bytes(BLUETOOTH), 0);
+    }
+
+    #[test]
+    fn test_realloc_keeps_tag() {
+        let allocator = Tagged::<_, 3>::new(BumpAllocator::new([0; 4096]));
+        allocator.set_quota(NETWORK, 300);
+        let layout = Layout::from_size_align(100, 8).unwrap();
+
+        unsafe {
+            let ptr = allocator.alloc_tagged(NETWORK, layout);
+            ptr.write_bytes(0xAB, 100);
+
+            // grown while the current tag is another one
+            let grown = allocator.with_tag(BLUETOOTH, || allocator.realloc(ptr, layout, 200));
+            assert!(!grown.is_null());
+            assert_eq!(*grown.add(99), 0xAB);
+            assert_eq!(allocator.report(), [0, 200, 0]);
+            assert_eq!(allocator.allocations(NETWORK), 1);
+
+            // and still held to the network quota
+            let grown_layout = Layout::from_size_align(200, 8).unwrap();
+            assert!(allocator.realloc(grown, grown_layout, 400).is_null());
+            assert_eq!(allocator.report(), [0, 200, 0]);
+            allocator.dealloc(grown, grown_layout);
+        }
+        assert_eq!(allocator.report(), [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_account_quotas() {
+        let allocator = Tagged::<_, 3>::new(BumpAllocator::new([0; 4096]));
+        allocator.set_quota(NETWORK, 256);
+        let network = allocator.account(NETWORK);
+        let bluetooth = allocator.account(BLUETOOTH);
+        let layout = Layout::from_size_align(100, 8).unwrap();
+
+        unsafe {
+            let a = network.alloc(layout);
+            let b = network.alloc(layout);
+            assert!(!a.is_null() && !b.is_null());
+            assert_eq!(network.remaining(), 56);
+
+            // over quota, while the heap and other accounts still have room
+            assert!(network.alloc(layout).is_null());
+            assert!(!bluetooth.alloc(layout).is_null());
+            assert_eq!(network.used(), 200);
+            assert_eq!(allocator.allocations(NETWORK), 2);
+
+            // freeing through the account, or the allocator, gives room back
+            network.dealloc(a, layout);
+            allocator.dealloc(b, layout);
+            assert_eq!(network.remaining(), 256);
+            assert!(!network.alloc(layout).is_null());
+        }
+        assert_eq!(allocator.report(), [0, 100, 100]);
+        assert_eq!(allocator.quota(SYSTEM), usize::MAX);
+    }
+
+    #[test]
+    fn test_soft_limit_calls_pressure_handler() {
+        use std::sync::atomic::AtomicUsize;
+        static PRESSURE: AtomicUsize = AtomicUsize::new(0);
+
+        fn on_pressure(tag: usize, bytes: usize) {
+            assert_eq!((tag, bytes), (NETWORK, 300));
+            PRESSURE.fetch_add(1, Ordering::Relaxed);
+        }
+
+        let allocator =
+            Tagged::<_, 2>::new(BumpAllocator::new([0; 4096])).with_pressure_handler(on_pressure);
+        allocator.set_soft_limit(NETWORK, 250);
+        allocator.set_quota(NETWORK, 400);
+        let network = allocator.account(NETWORK);
+        let layout = Layout::from_size_align(100, 8).unwrap();
+
+        unsafe {
+            let blocks = [network.alloc(layout), network.alloc(layout)];
+            assert_eq!(PRESSURE.load(Ordering::Relaxed), 0);
+
+            // crossing the soft limit succeeds, and calls the handler once
+            let third = network.alloc(layout);
+            assert!(!third.is_null());
+            assert_eq!(PRESSURE.load(Ordering::Relaxed), 1);
+            assert!(!network.alloc(layout).is_null());
+            assert_eq!(PRESSURE.load(Ordering::Relaxed), 1);
+
+            // the quota is the hard limit
+            assert!(network.alloc(layout).is_null());
+
+            // back under the soft limit, crossing it again calls it again
+            for block in blocks {
+                network.dealloc(block, layout);
+            }
+            network.dealloc(third, layout);
+            network.alloc(layout);
+            network.alloc(layout);
+            assert_eq!(PRESSURE.load(Ordering::Relaxed), 2);
+        }
+    }
+
+    #[test]
+    fn test_failed_allocation_not_charged() {
+        let allocator = Tagged::<_, 1>::new(BumpAllocator::new([0; 64]));
+        unsafe {
+            assert!(
+                allocator
+                    .alloc(Layout::from_size_align(100, 8).unwrap())
+                    .is_null()
+            );
+        }
+        assert_eq!(allocator.bytes(SYSTEM), 0);
+    }
+}
@@ -0,0 +1,34 @@
+extern crate std;
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// The system allocator, counting the bytes it has live. Used as the
+/// upstream of allocators that take their memory from another one.
+pub(crate) struct Counting {
+    live: AtomicUsize,
+}
+
+impl Counting {
+    pub(crate) const fn new() -> Self {
+        Self {
+            live: AtomicUsize::new(0),
+        }
+    }
+
+    pub(crate) fn live(&self) -> usize {
+        self.live.load(Ordering::Relaxed)
+    }
+}
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.live.fetch_add(layout.size(), Ordering::Relaxed);
+        unsafe { std::alloc::System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.live.fetch_sub(layout.size(), Ordering::Relaxed);
+        unsafe { std::alloc::System.dealloc(ptr, layout) }
+    }
+}
@@ -0,0 +1,182 @@
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::alloc::{GlobalAlloc, Layout};
+
+/// One step of a structured allocator workload.
+///
+/// Fields are kept small so arbitrary inputs stay within typical heap sizes:
+/// `align` is an exponent (`1 << (align % 13)`, i.e. up to 4096) and `index`
+/// picks a live block modulo the number of live blocks. `GlobalAlloc` leaves
+/// zero-size requests undefined, so an `Alloc` of size zero is skipped and a
+/// `Realloc` to size zero frees the block instead.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "fuzz", derive(arbitrary::Arbitrary))]
+pub enum HeapOp {
+    Alloc { size: u16, align: u8 },
+    Free { index: u16 },
+    Realloc { index: u16, new_size: u16 },
+    Validate,
+}
+
+struct Block {
+    ptr: *mut u8,
+    layout: Layout,
+    tag: u8,
+}
+
+impl Block {
+    unsafe fn check(&self, len: usize) {
+        let contents = unsafe { core::slice::from_raw_parts(self.ptr, len) };
+        assert!(
+            contents.iter().all(|&b| b == self.tag),
+            "block {:?} ({:?}) was corrupted",
+            self.ptr,
+            self.layout
+        );
+    }
+}
+
+/// Runs `ops` against `allocator`, panicking if a block is misaligned or its
+/// contents change while it is live. Failed allocations are skipped, and
+/// every block still live at the end is freed.
+pub fn run_heap_ops<A: GlobalAlloc>(allocator: &A, ops: &[HeapOp]) {
+    let mut live: Vec<Block> = Vec::new();
+    let mut next_tag: u8 = 0;
+
+    for op in ops {
+        match *op {
+            HeapOp::Alloc { size: 0, .. } => {}
+            HeapOp::Alloc { size, align } => {
+                let align = 1usize << (align % 13);
+                let layout = Layout::from_size_align(size as usize, align).unwrap();
+                let ptr = unsafe { allocator.alloc(layout) };
+                if ptr.is_null() {
+                    continue;
+                }
+                assert_eq!(ptr.addr() % align, 0, "{ptr:?} not aligned to {align}");
+                next_tag = next_tag.wrapping_add(1);
+                unsafe { ptr.write_bytes(next_tag, layout.size()) };
+                live.push(Block {
+                    ptr,
+                    layout,
+                    tag: next_tag,
+                });
+            }
+            HeapOp::Free { index } | HeapOp::Realloc { index, new_size: 0 } => {
+                if live.is_empty() {
+                    continue;
+                }
+                let block = live.swap_remove(index as usize % live.len());
+                unsafe {
+                    block.check(block.layout.size());
+                    allocator.dealloc(block.ptr, block.layout);
+                }
+            }
+            HeapOp::Realloc { index, new_size } => {
+                if live.is_empty() {
+                    continue;
+                }
+                let len = live.len();
+                let block = &mut live[index as usize % len];
+                let new_size = new_size as usize;
+                let ptr = unsafe { allocator.realloc(block.ptr, block.layout, new_size) };
+                if ptr.is_null() {
+                    continue;
+                }
+                let preserved = block.layout.size().min(new_size);
+                block.ptr = ptr;
+                block.layout = Layout::from_size_align(new_size, block.layout.align()).unwrap();
+                unsafe {
+                    block.check(preserved);
+                    ptr.write_bytes(block.tag, new_size);
+                }
+            }
+            HeapOp::Validate => {
+                for block in &live {
+                    unsafe { block.check(block.layout.size()) };
+                }
+            }
+        }
+    }
+
+    for block in live {
+        unsafe {
+            block.check(block.layout.size());
+            allocator.dealloc(block.ptr, block.layout);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::BumpAllocator;
+
+    #[test]
+    fn test_run_heap_ops() {
+        let allocator = BumpAllocator::new([0; 4096]);
+        let ops = [
+            HeapOp::Alloc { size: 64, align: 3 },
+            HeapOp::Alloc { size: 0, align: 0 },
+            HeapOp::Alloc { size: 100, align: 7 },
+            HeapOp::Realloc {
+                index: 0,
+                new_size: 200,
+            },
+            HeapOp::Validate,
+            HeapOp::Free { index: 1 },
+            HeapOp::Alloc {
+                size: 8192,
+                align: 4,
+            },
+            HeapOp::Realloc {
+                index: 5,
+                new_size: 16,
+            },
+            HeapOp::Validate,
+        ];
+
+        run_heap_ops(&allocator, &ops);
+    }
+
+    #[test]
+    fn test_zero_sizes_never_reach_the_allocator() {
+        struct Strict(BumpAllocator<4096>);
+
+        unsafe impl GlobalAlloc for Strict {
+            unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+                assert_ne!(layout.size(), 0, "alloc of size zero");
+                unsafe { self.0.alloc(layout) }
+            }
+
+            unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+                unsafe { self.0.dealloc(ptr, layout) }
+            }
+
+            unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+                assert_ne!(new_size, 0, "realloc to size zero");
+                unsafe { self.0.realloc(ptr, layout, new_size) }
+            }
+        }
+
+        let allocator = Strict(BumpAllocator::new([0; 4096]));
+        let ops = [
+            HeapOp::Alloc { size: 32, align: 3 },
+            HeapOp::Alloc { size: 0, align: 4 },
+            HeapOp::Alloc { size: 16, align: 0 },
+            HeapOp::Realloc {
+                index: 0,
+                new_size: 0,
+            },
+            HeapOp::Validate,
+            HeapOp::Realloc {
+                index: 0,
+                new_size: 64,
+            },
+            HeapOp::Validate,
+        ];
+
+        run_heap_ops(&allocator, &ops);
+    }
+}
@@ -1,13 +1,92 @@
+#[cfg(test)]
+mod counting;
+#[cfg(any(test, feature = "fuzz"))]
+mod heap_ops;
+
+#[cfg(test)]
+pub(crate) use counting::Counting;
+
+#[cfg(any(test, feature = "fuzz"))]
+pub use heap_ops::{HeapOp, run_heap_ops};
+
+/// Generates a conformance test suite for a [`GlobalAlloc`] implementation.
+///
+/// Invoke it inside a test module with two expressions, each building a fresh
+/// allocator every time it is evaluated:
+///
+/// - a regular allocator, which must be able to serve a few hundred kilobytes
+///   spread over a test (64 KiB of heap is enough for the crate's own
+///   allocators),
+/// - a small allocator, which must fail a 512-byte request but still fit at
+///   least one 64-byte block.
+///
+/// The tests need `std`, which the suite links itself, so the crate under
+/// test may be `no_std`.
+///
+/// By default every section runs. The core section (allocation, alignment,
+/// overlap, reuse, out-of-memory, coalescing and random workload tests) always
+/// runs; the others can be picked explicitly after a `;`:
+///
+/// - `integrity`: live blocks keep their contents byte for byte while their
+///   neighbours are freed, merged and split,
+/// - `realloc`: `realloc` preserves contents and alignment,
+/// - `concurrency`: the allocator is shared between threads through an
+///   `Arc`, so it must be `Send + Sync`,
+/// - `global`: smoke tests through `Vec`, `String` and `Box`. These use the
+///   test binary's global allocator, so they only exercise the allocator
+///   under test if it is installed as `#[global_allocator]`.
+///
+/// ```
+/// #[cfg(test)]
+/// mod tests {
+///     use simple_alloc::BumpAllocator;
+///
+///     // all sections
+///     simple_alloc::test_suite! {
+///         BumpAllocator::new([0; 65536]),
+///         BumpAllocator::new([0; 256])
+///     }
+/// }
+///
+/// #[cfg(test)]
+/// mod single_threaded_tests {
+///     use simple_alloc::BumpAllocator;
+///
+///     // skip the concurrency and global sections
+///     simple_alloc::test_suite! {
+///         BumpAllocator::new([0; 65536]),
+///         BumpAllocator::new([0; 256]);
+///         integrity, realloc
+///     }
+/// }
+/// ```
+///
+/// [`GlobalAlloc`]: core::alloc::GlobalAlloc
+#[macro_export]
 macro_rules! test_suite {
-	($make_allocator:expr, $make_small_allocator:expr) => {
+	($make_allocator:expr, $make_small_allocator:expr $(,)?) => {
+    $crate::test_suite! {
+        $make_allocator,
+        $make_small_allocator;
+        integrity, realloc, concurrency, global
+    }
+	};
+	($make_allocator:expr, $make_small_allocator:expr; $($section:ident),* $(,)?) => {
     extern crate std;
     use core::alloc::{GlobalAlloc, Layout};
-    use std::boxed::Box;
-    use std::string::String;
-    use std::sync::Arc;
     use std::vec::Vec;
 
+    $crate::__test_suite_section!(core, $make_allocator, $make_small_allocator);
+    $(
+        $crate::__test_suite_section!($section, $make_allocator, $make_small_allocator);
+    )*
+	};
+}
 
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __test_suite_section {
+	(core, $make_allocator:expr, $make_small_allocator:expr) => {
     // ========================================
     // Basic sanity
     // ========================================
@@ -33,17 +112,54 @@ macro_rules! test_suite {
         let allocator = $make_allocator;
 
         unsafe {
-            // Layout::from_size_align allows size 0
-            let layout = Layout::from_size_align(0, 1).unwrap();
-            let ptr = allocator.alloc(layout);
-            // Behavior for zero-size is implementation-defined,
-            // but it should not panic or return an invalid pointer
-            if !ptr.is_null() {
+            // zero-size requests get a non-null, well-aligned pointer; which
+            // one is up to the allocator
+            for align in [1, 2, 4, 8, 16, 32, 64, 128, 256] {
+                let layout = Layout::from_size_align(0, align).unwrap();
+                let ptr = allocator.alloc(layout);
+                assert!(!ptr.is_null(), "zero-size alloc with align {align} returned null");
+                assert_eq!(ptr.addr() % layout.align(), 0, "pointer {ptr:?} not aligned to {align}");
                 allocator.dealloc(ptr, layout);
             }
         }
     }
 
+    #[test]
+    fn test_zero_size_does_not_consume_heap() {
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        let zst_layout = Layout::from_size_align(0, 128).unwrap();
+
+        let count_allocations = |interleave_zst: bool| {
+            let allocator = $make_small_allocator;
+            let mut ptrs = Vec::new();
+            unsafe {
+                loop {
+                    if interleave_zst {
+                        let zst = allocator.alloc(zst_layout);
+                        assert!(!zst.is_null(), "zero-size alloc failed");
+                        ptrs.push((zst, zst_layout));
+                    }
+                    let ptr = allocator.alloc(layout);
+                    if ptr.is_null() {
+                        break;
+                    }
+                    ptrs.push((ptr, layout));
+                }
+                let count = ptrs.iter().filter(|(_, l)| l.size() != 0).count();
+                for (ptr, layout) in ptrs {
+                    allocator.dealloc(ptr, layout);
+                }
+                count
+            }
+        };
+
+        assert_eq!(
+            count_allocations(false),
+            count_allocations(true),
+            "zero-size allocations consumed heap space"
+        );
+    }
+
     #[test]
     fn test_single_byte() {
         let allocator = $make_allocator;
@@ -95,6 +211,32 @@ macro_rules! test_suite {
         }
     }
 
+    #[test]
+    fn test_page_alignments() {
+        let allocator = $make_allocator;
+
+        unsafe {
+            // above the heap's own alignment, the allocator has to pad
+            let layout = Layout::from_size_align(8, 4096).unwrap();
+            let ptr = allocator.alloc(layout);
+            assert!(!ptr.is_null());
+            assert_eq!(ptr as usize % 4096, 0, "pointer {ptr:?} not aligned to 4096");
+            ptr.write_bytes(0xAB, 8);
+
+            // may not fit at all in a 64 KiB heap, but must be aligned if it does
+            let huge = Layout::from_size_align(8, 65536).unwrap();
+            let huge_ptr = allocator.alloc(huge);
+            if !huge_ptr.is_null() {
+                assert_eq!(huge_ptr as usize % 65536, 0, "pointer {huge_ptr:?} not aligned to 65536");
+                huge_ptr.write_bytes(0xCD, 8);
+                allocator.dealloc(huge_ptr, huge);
+            }
+
+            assert!(std::slice::from_raw_parts(ptr, 8).iter().all(|&x| x == 0xAB));
+            allocator.dealloc(ptr, layout);
+        }
+    }
+
     #[test]
     fn test_many_different_alignments_at_once() {
         let allocator = $make_allocator;
@@ -390,7 +532,10 @@ macro_rules! test_suite {
     #[test]
     fn test_random_workload() {
         let allocator = $make_allocator;
-        let mut live: Vec<(*mut u8, Layout)> = Vec::new();
+        // each block is filled with its own tag, so data lost or shifted by
+        // realloc shows up as the wrong byte
+        let mut live: Vec<(*mut u8, Layout, u8)> = Vec::new();
+        let mut next_tag: u8 = 0;
 
         // Simple deterministic RNG using hashing (no external dependency)
         let mut seed: u64 = 12345;
@@ -401,10 +546,18 @@ macro_rules! test_suite {
             seed
         };
 
+        let check_tag = |ptr: *mut u8, len: usize, tag: u8| {
+            let bytes = unsafe { std::slice::from_raw_parts(ptr, len) };
+            assert!(
+                bytes.iter().all(|&b| b == tag),
+                "block {ptr:?} lost its contents (tag {tag:#x})"
+            );
+        };
+
         for _ in 0..10_000 {
-            let should_alloc = live.is_empty() || (next_rand() % 10) < 6;
+            let choice = if live.is_empty() { 0 } else { next_rand() % 10 };
 
-            if should_alloc {
+            if choice < 6 {
                 let size = (next_rand() % 1024 + 1) as usize;
                 let align_shift = (next_rand() % 8) as usize; // 0..7
                 let align = 1usize << align_shift; // 1, 2, 4, ..., 128
@@ -413,20 +566,36 @@ macro_rules! test_suite {
                 unsafe {
                     let ptr = allocator.alloc(layout);
                     if !ptr.is_null() {
-                        // touch the memory
-                        ptr.write_bytes(0xAB, size);
+                        next_tag = next_tag.wrapping_add(1);
+                        ptr.write_bytes(next_tag, size);
                         assert_eq!(
                             ptr as usize % align,
                             0,
                             "misaligned: {ptr:?} % {align} != 0"
                         );
-                        live.push((ptr, layout));
+                        live.push((ptr, layout, next_tag));
+                    }
+                }
+            } else if choice < 7 {
+                // resize a random allocation, growing or shrinking
+                let idx = (next_rand() as usize) % live.len();
+                let (ptr, layout, tag) = live[idx];
+                let new_size = (next_rand() % 2048 + 1) as usize;
+                unsafe {
+                    let new_ptr = allocator.realloc(ptr, layout, new_size);
+                    // on failure the original block is untouched and stays live
+                    if !new_ptr.is_null() {
+                        check_tag(new_ptr, layout.size().min(new_size), tag);
+                        new_ptr.write_bytes(tag, new_size);
+                        let new_layout = Layout::from_size_align(new_size, layout.align()).unwrap();
+                        live[idx] = (new_ptr, new_layout, tag);
                     }
                 }
             } else {
                 // free a random allocation
                 let idx = (next_rand() as usize) % live.len();
-                let (ptr, layout) = live.swap_remove(idx);
+                let (ptr, layout, tag) = live.swap_remove(idx);
+                check_tag(ptr, layout.size(), tag);
                 unsafe {
                     allocator.dealloc(ptr, layout);
                 }
@@ -434,20 +603,234 @@ macro_rules! test_suite {
         }
 
         // cleanup remaining
-        for (ptr, layout) in live {
+        for (ptr, layout, _) in live {
             unsafe {
                 allocator.dealloc(ptr, layout);
             }
         }
     }
 
+	};
+	(integrity, $make_allocator:expr, $make_small_allocator:expr) => {
+    // ========================================
+    // Data integrity across coalescing
+    // ========================================
+
+    // Each byte depends on its block and offset, so a live block that a
+    // merged or split neighbour ran into, or that moved, shows up as a wrong
+    // byte rather than just a suspicious pointer.
+
+    #[test]
+    fn test_coalescing_keeps_live_neighbors() {
+        let allocator = $make_allocator;
+        let layout = Layout::from_size_align(128, 8).unwrap();
+
+        let pattern = |tag: u8, i: usize| tag.wrapping_mul(37).wrapping_add(i as u8);
+        let fill = |ptr: *mut u8, len: usize, tag: u8| {
+            for i in 0..len {
+                unsafe { *ptr.add(i) = pattern(tag, i) };
+            }
+        };
+        let check = |ptr: *mut u8, len: usize, tag: u8| {
+            for i in 0..len {
+                let byte = unsafe { *ptr.add(i) };
+                assert_eq!(byte, pattern(tag, i), "byte {i} of block {tag} changed");
+            }
+        };
+
+        unsafe {
+            let blocks: Vec<*mut u8> = (0..8).map(|_| allocator.alloc(layout)).collect();
+            for (tag, &ptr) in blocks.iter().enumerate() {
+                assert!(!ptr.is_null());
+                fill(ptr, 128, tag as u8);
+            }
+
+            // free two neighbours, which may merge, and a lone block
+            allocator.dealloc(blocks[2], layout);
+            allocator.dealloc(blocks[3], layout);
+            allocator.dealloc(blocks[5], layout);
+            for tag in [0, 1, 4, 6, 7] {
+                check(blocks[tag], 128, tag as u8);
+            }
+
+            // these may be split out of the freed space
+            let wide = Layout::from_size_align(200, 8).unwrap();
+            let narrow = Layout::from_size_align(48, 8).unwrap();
+            let a = allocator.alloc(wide);
+            let b = allocator.alloc(narrow);
+            let c = allocator.alloc(narrow);
+            assert!(!a.is_null() && !b.is_null() && !c.is_null());
+            fill(a, 200, 100);
+            fill(b, 48, 101);
+            fill(c, 48, 102);
+            for tag in [0, 1, 4, 6, 7] {
+                check(blocks[tag], 128, tag as u8);
+            }
+
+            // free around the new blocks, so the holes merge again
+            allocator.dealloc(blocks[4], layout);
+            allocator.dealloc(b, narrow);
+            let big = Layout::from_size_align(256, 8).unwrap();
+            let d = allocator.alloc(big);
+            assert!(!d.is_null());
+            fill(d, 256, 103);
+
+            for tag in [0, 1, 6, 7] {
+                check(blocks[tag], 128, tag as u8);
+            }
+            check(a, 200, 100);
+            check(c, 48, 102);
+            check(d, 256, 103);
+
+            for tag in [0, 1, 6, 7] {
+                allocator.dealloc(blocks[tag], layout);
+            }
+            allocator.dealloc(a, wide);
+            allocator.dealloc(c, narrow);
+            allocator.dealloc(d, big);
+        }
+    }
+
+    #[test]
+    fn test_split_blocks_keep_contents() {
+        let allocator = $make_allocator;
+        let mut live: Vec<(*mut u8, Layout, u8)> = Vec::new();
+
+        let pattern = |tag: u8, i: usize| tag.wrapping_mul(37).wrapping_add(i as u8);
+        let alloc_filled = |live: &mut Vec<(*mut u8, Layout, u8)>, layout: Layout, tag: u8| {
+            let ptr = unsafe { allocator.alloc(layout) };
+            assert!(!ptr.is_null());
+            for i in 0..layout.size() {
+                unsafe { *ptr.add(i) = pattern(tag, i) };
+            }
+            live.push((ptr, layout, tag));
+        };
+        let check_all = |live: &Vec<(*mut u8, Layout, u8)>| {
+            for &(ptr, layout, tag) in live {
+                for i in 0..layout.size() {
+                    let byte = unsafe { *ptr.add(i) };
+                    assert_eq!(byte, pattern(tag, i), "byte {i} of block {tag} changed");
+                }
+            }
+        };
+
+        unsafe {
+            // one large free block for the small ones to be split from
+            let large = Layout::from_size_align(4096, 8).unwrap();
+            let ptr = allocator.alloc(large);
+            assert!(!ptr.is_null());
+            allocator.dealloc(ptr, large);
+
+            // alternate alignments, so splits leave padding between blocks
+            for tag in 0..16u8 {
+                let align = if tag % 2 == 0 { 8 } else { 64 };
+                let layout = Layout::from_size_align(16 + tag as usize * 13, align).unwrap();
+                alloc_filled(&mut live, layout, tag);
+            }
+            check_all(&live);
+
+            // free every other block, then refill the holes with other sizes
+            let mut index = 0;
+            live.retain(|&(ptr, layout, _)| {
+                index += 1;
+                if index % 2 == 0 {
+                    allocator.dealloc(ptr, layout);
+                    return false;
+                }
+                true
+            });
+            check_all(&live);
+            for tag in 16..24u8 {
+                let layout = Layout::from_size_align(24 + tag as usize * 7, 8).unwrap();
+                alloc_filled(&mut live, layout, tag);
+            }
+            check_all(&live);
+
+            // free from the middle outwards, checking the rest each time
+            while !live.is_empty() {
+                let (ptr, layout, _) = live.remove(live.len() / 2);
+                allocator.dealloc(ptr, layout);
+                check_all(&live);
+            }
+        }
+    }
+	};
+	(realloc, $make_allocator:expr, $make_small_allocator:expr) => {
+    // ========================================
+    // Realloc
+    // ========================================
+
+    #[test]
+    fn test_realloc_grow_preserves_contents() {
+        let allocator = $make_allocator;
+
+        unsafe {
+            let layout = Layout::from_size_align(64, 8).unwrap();
+            let ptr = allocator.alloc(layout);
+            assert!(!ptr.is_null());
+            for i in 0..64 {
+                *ptr.add(i) = i as u8;
+            }
+
+            let grown = allocator.realloc(ptr, layout, 4096);
+            assert!(!grown.is_null());
+            for i in 0..64 {
+                assert_eq!(*grown.add(i), i as u8, "byte {i} lost when growing");
+            }
+            // the new tail must be writable
+            grown.add(64).write_bytes(0xAB, 4096 - 64);
+
+            allocator.dealloc(grown, Layout::from_size_align(4096, 8).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_realloc_shrink_preserves_prefix() {
+        let allocator = $make_allocator;
+
+        unsafe {
+            let layout = Layout::from_size_align(256, 8).unwrap();
+            let ptr = allocator.alloc(layout);
+            assert!(!ptr.is_null());
+            for i in 0..256 {
+                *ptr.add(i) = i as u8;
+            }
+
+            let shrunk = allocator.realloc(ptr, layout, 32);
+            assert!(!shrunk.is_null());
+            for i in 0..32 {
+                assert_eq!(*shrunk.add(i), i as u8, "byte {i} lost when shrinking");
+            }
+
+            allocator.dealloc(shrunk, Layout::from_size_align(32, 8).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_realloc_preserves_alignment() {
+        let allocator = $make_allocator;
+
+        unsafe {
+            let layout = Layout::from_size_align(16, 128).unwrap();
+            let ptr = allocator.alloc(layout);
+            assert!(!ptr.is_null());
+
+            let grown = allocator.realloc(ptr, layout, 1024);
+            assert!(!grown.is_null());
+            assert_eq!(grown as usize % 128, 0, "pointer {grown:?} not aligned to 128");
+
+            allocator.dealloc(grown, Layout::from_size_align(1024, 128).unwrap());
+        }
+    }
+	};
+	(concurrency, $make_allocator:expr, $make_small_allocator:expr) => {
     // ========================================
     // Thread safety
     // ========================================
 
     #[test]
     fn test_concurrent() {
-        let allocator = Arc::new($make_allocator);
+        let allocator = std::sync::Arc::new($make_allocator);
 
         let handles: Vec<_> = (0..8)
             .map(|_| {
@@ -473,7 +856,7 @@ macro_rules! test_suite {
 
     #[test]
     fn test_concurrent_mixed_sizes() {
-        let allocator = Arc::new($make_allocator);
+        let allocator = std::sync::Arc::new($make_allocator);
 
         let handles: Vec<_> = (0..8)
             .map(|thread_id| {
@@ -522,14 +905,86 @@ macro_rules! test_suite {
         }
     }
 
+    #[test]
+    fn test_concurrent_realloc() {
+        let allocator = std::sync::Arc::new($make_allocator);
+        let start = std::sync::Arc::new(std::sync::Barrier::new(8));
+
+        let handles: Vec<_> = (0..8)
+            .map(|thread_id| {
+                let alloc = allocator.clone();
+                let start = start.clone();
+                std::thread::spawn(move || {
+                    let tag = thread_id as u8;
+                    let check = |ptr: *mut u8, len: usize| {
+                        let bytes = unsafe { std::slice::from_raw_parts(ptr, len) };
+                        assert!(
+                            bytes.iter().all(|&b| b == tag),
+                            "realloc lost the contents of a block in thread {thread_id}"
+                        );
+                    };
+                    start.wait();
+
+                    if thread_id % 2 == 1 {
+                        // churn alongside the threads resizing their buffers
+                        for i in 0..500 {
+                            let size = (thread_id * 31 + i * 17) % 256 + 1;
+                            let layout = Layout::from_size_align(size, 8).unwrap();
+                            unsafe {
+                                let ptr = alloc.alloc(layout);
+                                if !ptr.is_null() {
+                                    ptr.write_bytes(tag, size);
+                                    check(ptr, size);
+                                    alloc.dealloc(ptr, layout);
+                                }
+                            }
+                        }
+                        return;
+                    }
+
+                    let mut layout = Layout::from_size_align(16, 8).unwrap();
+                    let mut ptr = unsafe { alloc.alloc(layout) };
+                    if ptr.is_null() {
+                        return;
+                    }
+                    unsafe { ptr.write_bytes(tag, 16) };
+                    for i in 0..500 {
+                        // alternately grow and shrink
+                        let new_size = if i % 2 == 0 {
+                            (thread_id * 13 + i * 37) % 1024 + 64
+                        } else {
+                            (i * 11) % 64 + 1
+                        };
+                        let new_ptr = unsafe { alloc.realloc(ptr, layout, new_size) };
+                        if new_ptr.is_null() {
+                            // the original block is untouched and stays live
+                            check(ptr, layout.size());
+                            continue;
+                        }
+                        check(new_ptr, layout.size().min(new_size));
+                        unsafe { new_ptr.write_bytes(tag, new_size) };
+                        ptr = new_ptr;
+                        layout = Layout::from_size_align(new_size, 8).unwrap();
+                    }
+                    check(ptr, layout.size());
+                    unsafe { alloc.dealloc(ptr, layout) };
+                })
+            })
+            .collect();
+
+        for h in handles {
+            h.join().unwrap();
+        }
+    }
+
+	};
+	(global, $make_allocator:expr, $make_small_allocator:expr) => {
     // ========================================
     // Use as global allocator
     // ========================================
 
-    // Uncomment to test as the actual global allocator:
-    //
-    // #[global_allocator]
-    // static ALLOCATOR: MyAllocator = MyAllocator::new();
+    // These run against the test binary's own global allocator, not the
+    // allocator under test.
 
     #[test]
     fn test_as_global_with_vec() {
@@ -542,10 +997,10 @@ macro_rules! test_suite {
 
     #[test]
     fn test_as_global_with_string() {
-        let s = String::from("hello world, this is a string that will trigger allocation");
+        let s = std::string::String::from("hello world, this is a string that will trigger allocation");
         assert!(s.contains("hello"));
 
-        let mut s2 = String::new();
+        let mut s2 = std::string::String::new();
         for i in 0..100 {
             s2.push_str(&std::format!("item {i} "));
         }
@@ -554,7 +1009,7 @@ macro_rules! test_suite {
 
     #[test]
     fn test_as_global_with_box() {
-        let b = Box::new([0u8; 4096]);
+        let b = std::boxed::Box::new([0u8; 4096]);
         assert_eq!(b[0], 0);
         assert_eq!(b[4095], 0);
     }
@@ -580,6 +1035,5 @@ macro_rules! test_suite {
         }
         assert_eq!(v.len(), 10_100);
     }
-}
-
+	};
 }
@@ -0,0 +1,215 @@
+use core::alloc::{GlobalAlloc, Layout};
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+use std::time::Instant;
+
+/// Wraps an allocator and records how long each of its operations takes, so
+/// latency claims can be checked on the real workload.
+///
+/// Latencies go into log-bucketed histograms, one per [`Operation`]: bucket
+/// `i` counts operations that took less than `2^i` nanoseconds (and at least
+/// `2^(i-1)`). Recording is lock-free, and each thread records into one of
+/// 16 cache-line-aligned copies of the histograms, which are summed when
+/// read, so timing a multithreaded allocator doesn't make every thread
+/// contend on the same few counters.
+#[derive(Debug)]
+pub struct Timed<A> {
+    inner: A,
+    shards: [[Histogram; 3]; SHARDS],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Operation {
+    Alloc,
+    Dealloc,
+    Realloc,
+}
+
+const BUCKETS: usize = 64;
+
+const SHARDS: usize = 16;
+
+std::thread_local! {
+    // Assigned round-robin on first use, so the first SHARDS threads never
+    // share one.
+    static SHARD: core::cell::Cell<usize> = const { core::cell::Cell::new(usize::MAX) };
+}
+
+fn shard() -> usize {
+    use core::sync::atomic::AtomicUsize;
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+
+    let shard = SHARD.get();
+    if shard != usize::MAX {
+        return shard;
+    }
+    let shard = NEXT.fetch_add(1, Ordering::Relaxed) % SHARDS;
+    SHARD.set(shard);
+    shard
+}
+
+// 128 rather than 64 so the spatial prefetcher doesn't pair up lines of
+// neighbouring shards
+#[derive(Debug)]
+#[repr(align(128))]
+struct Histogram {
+    buckets: [AtomicU64; BUCKETS],
+}
+
+impl Histogram {
+    const fn new() -> Self {
+        Self {
+            buckets: [const { AtomicU64::new(0) }; BUCKETS],
+        }
+    }
+
+    fn record(&self, elapsed: Duration) {
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        let bucket = (u64::BITS - nanos.leading_zeros()) as usize;
+        self.buckets[bucket.min(BUCKETS - 1)].fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// A snapshot of the latencies of one [`Operation`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LatencyHistogram {
+    buckets: [u64; BUCKETS],
+}
+
+impl LatencyHistogram {
+    /// Number of operations recorded.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Upper bound of the latency under which a fraction `quantile` (between
+    /// 0 and 1) of the operations completed, or `None` if there are none.
+    pub fn quantile(&self, quantile: f64) -> Option<Duration> {
+        let count = self.count();
+        if count == 0 {
+            return None;
+        }
+        let rank = ((quantile.clamp(0.0, 1.0) * count as f64).ceil() as u64).max(1);
+        let mut seen = 0;
+        self.buckets().find_map(|(bound, n)| {
+            seen += n;
+            (seen >= rank).then_some(bound)
+        })
+    }
+
+    /// The non-empty buckets as `(upper bound, count)` pairs, fastest first.
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| **n != 0)
+            .map(|(i, n)| (Duration::from_nanos(1u64 << i.min(63)), *n))
+    }
+}
+
+impl<A> Timed<A> {
+    pub const fn new(inner: A) -> Self {
+        Self {
+            inner,
+            shards: [const { [const { Histogram::new() }; 3] }; SHARDS],
+        }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    pub fn latency(&self, operation: Operation) -> LatencyHistogram {
+        LatencyHistogram {
+            buckets: core::array::from_fn(|i| {
+                self.shards
+                    .iter()
+                    .map(|shard| shard[operation as usize].buckets[i].load(Ordering::Relaxed))
+                    .sum()
+            }),
+        }
+    }
+
+    fn time<R>(&self, operation: Operation, f: impl FnOnce() -> R) -> R {
+        let start = Instant::now();
+        let result = f();
+        self.shards[shard()][operation as usize].record(start.elapsed());
+        result
+    }
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Timed<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.time(Operation::Alloc, || unsafe { self.inner.alloc(layout) })
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.time(Operation::Dealloc, || unsafe {
+            self.inner.dealloc(ptr, layout)
+        })
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        self.time(Operation::Realloc, || unsafe {
+            self.inner.realloc(ptr, layout, new_size)
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::BumpAllocator;
+
+    test_suite! {
+        Timed::new(BumpAllocator::new([0; 65536])),
+        Timed::new(BumpAllocator::new([0; 256]))
+    }
+
+    #[test]
+    fn test_latencies_recorded_per_operation() {
+        let allocator = Timed::new(BumpAllocator::new([0; 4096]));
+        let layout = Layout::from_size_align(64, 8).unwrap();
+
+        unsafe {
+            for _ in 0..10 {
+                let ptr = allocator.alloc(layout);
+                allocator.dealloc(ptr, layout);
+            }
+            let ptr = allocator.alloc(layout);
+            allocator.realloc(ptr, layout, 128);
+        }
+
+        assert_eq!(allocator.latency(Operation::Alloc).count(), 11);
+        assert_eq!(allocator.latency(Operation::Dealloc).count(), 10);
+        assert_eq!(allocator.latency(Operation::Realloc).count(), 1);
+
+        let alloc = allocator.latency(Operation::Alloc);
+        let median = alloc.quantile(0.5).unwrap();
+        let max = alloc.quantile(1.0).unwrap();
+        assert!(median <= max);
+        assert_eq!(alloc.buckets().last().unwrap().0, max);
+        assert_eq!(Timed::new(()).latency(Operation::Alloc).quantile(0.5), None);
+    }
+
+    #[test]
+    fn test_latencies_summed_across_threads() {
+        let allocator = Timed::new(BumpAllocator::new([0; 65536]));
+        let layout = Layout::from_size_align(16, 8).unwrap();
+
+        std::thread::scope(|scope| {
+            for _ in 0..SHARDS + 4 {
+                scope.spawn(|| unsafe {
+                    for _ in 0..50 {
+                        allocator.alloc(layout);
+                    }
+                });
+            }
+        });
+
+        assert_eq!(
+            allocator.latency(Operation::Alloc).count(),
+            50 * (SHARDS as u64 + 4)
+        );
+    }
+}
@@ -0,0 +1,205 @@
+use core::alloc::{GlobalAlloc, Layout};
+
+/// Wraps an allocator and emits `tracing` events for allocations of at
+/// least `threshold` bytes and for failed allocations, so allocator
+/// behavior shows up in the application's tracing pipeline.
+///
+/// With the `tracing` feature, allocators with an [`OomPolicy`] also emit an
+/// event for every failed allocation, and
+/// [`UnsyncPoolResource::trim`](crate::UnsyncPoolResource::trim) reports
+/// what it released.
+///
+/// The subscriber may allocate, possibly from the allocator being traced.
+/// Events raised while another one is being emitted on the same thread are
+/// dropped, so this can't recurse.
+///
+/// [`OomPolicy`]: crate::OomPolicy
+#[derive(Debug)]
+pub struct Traced<A> {
+    inner: A,
+    threshold: usize,
+}
+
+impl<A> Traced<A> {
+    pub const fn new(inner: A, threshold: usize) -> Self {
+        Self { inner, threshold }
+    }
+
+    pub fn inner(&self) -> &A {
+        &self.inner
+    }
+
+    fn allocated(&self, layout: Layout, ptr: *mut u8) {
+        if ptr.is_null() {
+            emit(|| {
+                tracing::warn!(
+                    size = layout.size(),
+                    align = layout.align(),
+                    "allocation failed"
+                )
+            });
+        } else if layout.size() >= self.threshold {
+            emit(|| {
+                tracing::info!(
+                    size = layout.size(),
+                    align = layout.align(),
+                    "large allocation"
+                )
+            });
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+std::thread_local! {
+    static EMITTING: core::cell::Cell<bool> = const { core::cell::Cell::new(false) };
+}
+
+/// Without std there is a single flag for the whole program, which suits
+/// single-core targets.
+#[cfg(not(feature = "std"))]
+static EMITTING: Emitting = Emitting(core::sync::atomic::AtomicBool::new(false));
+
+#[cfg(not(feature = "std"))]
+struct Emitting(core::sync::atomic::AtomicBool);
+
+#[cfg(not(feature = "std"))]
+impl Emitting {
+    fn replace(&self, emitting: bool) -> bool {
+        self.0.swap(emitting, core::sync::atomic::Ordering::Relaxed)
+    }
+
+    fn set(&self, emitting: bool) {
+        self.0
+            .store(emitting, core::sync::atomic::Ordering::Relaxed)
+    }
+}
+
+/// Runs `f`, which emits an event, unless an event is already being emitted.
+pub(crate) fn emit(f: impl FnOnce()) {
+    struct Done;
+
+    impl Drop for Done {
+        fn drop(&mut self) {
+            EMITTING.set(false);
+        }
+    }
+
+    if EMITTING.replace(true) {
+        return;
+    }
+    let _done = Done;
+    f();
+}
+
+unsafe impl<A: GlobalAlloc> GlobalAlloc for Traced<A> {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { self.inner.alloc(layout) };
+        self.allocated(layout, ptr);
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.inner.dealloc(ptr, layout) }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        let new_ptr = unsafe { self.inner.realloc(ptr, layout, new_size) };
+        let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+        self.allocated(new_layout, new_ptr);
+        new_ptr
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod test {
+    use super::*;
+    use crate::BumpAllocator;
+    use std::string::String;
+    use std::sync::Mutex;
+    use tracing::field::{Field, Visit};
+    use tracing::span::{Attributes, Id, Record};
+    use tracing::{Event, Metadata, Subscriber};
+
+    test_suite! {
+        Traced::new(BumpAllocator::new([0; 65536]), 1024),
+        Traced::new(BumpAllocator::new([0; 256]), 1024)
+    }
+
+    /// Keeps every event as `level message field=value...`.
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<String>>);
+
+    struct Line(String);
+
+    impl Visit for Line {
+        fn record_debug(&mut self, field: &Field, value: &dyn core::fmt::Debug) {
+            use core::fmt::Write;
+            match field.name() {
+                "message" => write!(self.0, " {value:?}").unwrap(),
+                name => write!(self.0, " {name}={value:?}").unwrap(),
+            }
+        }
+    }
+
+    impl Subscriber for &'static Recorder {
+        fn enabled(&self, _: &Metadata<'_>) -> bool {
+            true
+        }
+
+        fn new_span(&self, _: &Attributes<'_>) -> Id {
+            Id::from_u64(1)
+        }
+
+        fn record(&self, _: &Id, _: &Record<'_>) {}
+
+        fn record_follows_from(&self, _: &Id, _: &Id) {}
+
+        fn event(&self, event: &Event<'_>) {
+            let mut line = Line(String::from(event.metadata().level().as_str()));
+            event.record(&mut line);
+            self.0.lock().unwrap().push(line.0);
+        }
+
+        fn enter(&self, _: &Id) {}
+
+        fn exit(&self, _: &Id) {}
+    }
+
+    fn recorded(f: impl FnOnce()) -> Vec<String> {
+        let recorder: &'static Recorder = std::boxed::Box::leak(std::boxed::Box::default());
+        tracing::subscriber::with_default(recorder, f);
+        recorder.0.lock().unwrap().clone()
+    }
+
+    #[test]
+    fn test_events() {
+        let allocator = Traced::new(BumpAllocator::new([0; 4096]), 1024);
+        let events = recorded(|| unsafe {
+            allocator.alloc(Layout::from_size_align(1023, 1).unwrap());
+            allocator.alloc(Layout::from_size_align(2048, 8).unwrap());
+            allocator.alloc(Layout::from_size_align(2048, 8).unwrap());
+        });
+        assert_eq!(
+            events,
+            [
+                "INFO large allocation size=2048 align=8",
+                // from the bump allocator's OOM policy, then from the wrapper
+                "WARN out of memory size=2048 align=8 used=3072 capacity=4096",
+                "WARN allocation failed size=2048 align=8",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_oom_policy_event() {
+        let allocator = BumpAllocator::new([0; 64]);
+        let events = recorded(|| unsafe {
+            allocator.alloc(Layout::from_size_align(100, 4).unwrap());
+        });
+        assert_eq!(
+            events,
+            ["WARN out of memory size=100 align=4 used=0 capacity=64"]
+        );
+    }
+}
@@ -0,0 +1,60 @@
+//! Valgrind client requests, so memcheck tracks blocks handed out from our
+//! heaps as individual allocations. The magic instruction sequences are
+//! no-ops when the program is not running under Valgrind.
+
+const MALLOCLIKE_BLOCK: usize = 0x1301;
+const FREELIKE_BLOCK: usize = 0x1302;
+const RESIZEINPLACE_BLOCK: usize = 0x130b;
+
+pub(crate) fn malloclike_block(ptr: *mut u8, size: usize) {
+    client_request(MALLOCLIKE_BLOCK, [ptr.addr(), size, 0, 0, 0]);
+}
+
+pub(crate) fn freelike_block(ptr: *mut u8) {
+    client_request(FREELIKE_BLOCK, [ptr.addr(), 0, 0, 0, 0]);
+}
+
+pub(crate) fn resizeinplace_block(ptr: *mut u8, old_size: usize, new_size: usize) {
+    client_request(RESIZEINPLACE_BLOCK, [ptr.addr(), old_size, new_size, 0, 0]);
+}
+
+#[cfg(target_arch = "x86_64")]
+fn client_request(request: usize, args: [usize; 5]) -> usize {
+    let args = [request, args[0], args[1], args[2], args[3], args[4]];
+    let result;
+    unsafe {
+        core::arch::asm!(
+            "rol rdi, 3",
+            "rol rdi, 13",
+            "rol rdi, 61",
+            "rol rdi, 51",
+            "xchg rbx, rbx",
+            in("rax") args.as_ptr(),
+            inout("rdx") 0usize => result,
+        );
+    }
+    result
+}
+
+#[cfg(target_arch = "aarch64")]
+fn client_request(request: usize, args: [usize; 5]) -> usize {
+    let args = [request, args[0], args[1], args[2], args[3], args[4]];
+    let result;
+    unsafe {
+        core::arch::asm!(
+            "ror x12, x12, #3",
+            "ror x12, x12, #13",
+            "ror x12, x12, #51",
+            "ror x12, x12, #61",
+            "orr x10, x10, x10",
+            in("x4") args.as_ptr(),
+            inout("x3") 0usize => result,
+        );
+    }
+    result
+}
+
+#[cfg(not(any(target_arch = "x86_64", target_arch = "aarch64")))]
+fn client_request(_request: usize, _args: [usize; 5]) -> usize {
+    0
+}
@@ -0,0 +1,299 @@
+//! A code-size-first global allocator for `wasm32`.
+//!
+//! Blocks are rounded up to a power of two. Each size class has a free
+//! list, and new blocks are bumped from the end of linear memory, which is
+//! grown with `memory.grow` when needed. There is no splitting, coalescing
+//! or statistics: the point is the smallest possible `.wasm`.
+//!
+//! The `wasm` feature only adds this allocator; the rest of the crate is
+//! still compiled, and whatever the binary doesn't use is left out at link
+//! time. The size class logic is also built for tests on the host, against
+//! a fake linear memory.
+
+#[cfg(target_arch = "wasm32")]
+use core::alloc::GlobalAlloc;
+use core::alloc::Layout;
+#[cfg(target_arch = "wasm32")]
+use core::arch::wasm32;
+#[cfg(target_arch = "wasm32")]
+use core::cell::UnsafeCell;
+use core::ptr;
+
+use crate::intrusive::FreeList;
+#[cfg(target_arch = "wasm32")]
+use crate::zero_size;
+
+#[cfg(target_feature = "atomics")]
+compile_error!("WasmAllocator is single-threaded and doesn't support wasm threads");
+
+const PAGE_SIZE: usize = 65536;
+const MIN_CLASS_SHIFT: u32 = 3;
+const CLASS_COUNT: usize = (usize::BITS - MIN_CLASS_SHIFT) as usize;
+
+/// Install it with `#[global_allocator] static A: WasmAllocator =
+/// WasmAllocator::new();`.
+#[cfg(target_arch = "wasm32")]
+#[derive(Debug)]
+pub struct WasmAllocator {
+    state: UnsafeCell<State<LinearMemory>>,
+}
+
+#[derive(Debug)]
+struct State<M> {
+    memory: M,
+    // zero until the first allocation
+    next: usize,
+    end: usize,
+    free_lists: [FreeList; CLASS_COUNT],
+}
+
+/// Where `State` gets its memory from: linear memory on wasm32, a buffer in
+/// tests.
+trait Memory {
+    /// Address of the first byte free for the heap.
+    fn heap_base(&self) -> usize;
+    /// Address just past the end of the memory.
+    fn end(&self) -> usize;
+    /// Adds `pages` pages at the end, returning the previous end, or `None`
+    /// if the memory can't grow.
+    fn grow(&mut self, pages: usize) -> Option<usize>;
+}
+
+#[cfg(target_arch = "wasm32")]
+#[derive(Debug)]
+struct LinearMemory;
+
+#[cfg(target_arch = "wasm32")]
+unsafe extern "C" {
+    // set by wasm-ld to the end of the static data and stack
+    static __heap_base: u8;
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Memory for LinearMemory {
+    fn heap_base(&self) -> usize {
+        ptr::addr_of!(__heap_base).addr()
+    }
+
+    fn end(&self) -> usize {
+        wasm32::memory_size(0) * PAGE_SIZE
+    }
+
+    fn grow(&mut self, pages: usize) -> Option<usize> {
+        match wasm32::memory_grow(0, pages) {
+            usize::MAX => None,
+            previous => Some(previous * PAGE_SIZE),
+        }
+    }
+}
+
+// wasm32 without the atomics feature has a single thread.
+#[cfg(target_arch = "wasm32")]
+unsafe impl Sync for WasmAllocator {}
+
+#[cfg(target_arch = "wasm32")]
+impl WasmAllocator {
+    pub const fn new() -> Self {
+        Self {
+            state: UnsafeCell::new(State::new(LinearMemory)),
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Default for WasmAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn class_of(layout: Layout) -> usize {
+    let size = layout.size().max(layout.align()).next_power_of_two();
+    (size.trailing_zeros().max(MIN_CLASS_SHIFT) - MIN_CLASS_SHIFT) as usize
+}
+
+impl<M: Memory> State<M> {
+    const fn new(memory: M) -> Self {
+        Self {
+            memory,
+            next: 0,
+            end: 0,
+            free_lists: [const { FreeList::new() }; CLASS_COUNT],
+        }
+    }
+
+    fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let class = class_of(layout);
+        let block = self.free_lists[class].pop();
+        if block.is_null() {
+            return self.bump(class);
+        }
+        block
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must be a block of `layout` from `alloc`, not freed yet.
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        // the smallest class holds a pointer
+        unsafe { self.free_lists[class_of(layout)].push(ptr) };
+    }
+
+    fn bump(&mut self, class: usize) -> *mut u8 {
+        if self.next == 0 {
+            self.next = self.memory.heap_base();
+            self.end = self.memory.end();
+        }
+        let size = 1usize << (class as u32 + MIN_CLASS_SHIFT);
+        loop {
+            // blocks are aligned to their size
+            let Some(start) = self.next.checked_next_multiple_of(size) else {
+                return ptr::null_mut();
+            };
+            let Some(end) = start.checked_add(size) else {
+                return ptr::null_mut();
+            };
+            if end <= self.end {
+                self.next = end;
+                return ptr::with_exposed_provenance_mut(start);
+            }
+            let pages = (end - self.end).div_ceil(PAGE_SIZE);
+            let Some(previous_end) = self.memory.grow(pages) else {
+                return ptr::null_mut();
+            };
+            if previous_end != self.end {
+                // something else grew the memory since, so only the pages
+                // just added are ours
+                self.next = previous_end;
+            }
+            self.end = previous_end + pages * PAGE_SIZE;
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+unsafe impl GlobalAlloc for WasmAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == 0 {
+            return zero_size::dangling(layout);
+        }
+        unsafe { (*self.state.get()).alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if zero_size::is_dangling(ptr, layout) {
+            return;
+        }
+        unsafe { (*self.state.get()).dealloc(ptr, layout) }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    extern crate std;
+
+    /// A page-aligned buffer of `max_pages` pages standing in for linear
+    /// memory, of which the first `pages` are in use.
+    struct FakeMemory {
+        base: *mut u8,
+        pages: usize,
+        max_pages: usize,
+    }
+
+    impl FakeMemory {
+        fn new(pages: usize, max_pages: usize) -> Self {
+            let base = unsafe { std::alloc::alloc(Self::layout(max_pages)) };
+            assert!(!base.is_null());
+            base.expose_provenance();
+            Self {
+                base,
+                pages,
+                max_pages,
+            }
+        }
+
+        fn layout(max_pages: usize) -> Layout {
+            Layout::from_size_align(max_pages * PAGE_SIZE, PAGE_SIZE).unwrap()
+        }
+    }
+
+    impl Drop for FakeMemory {
+        fn drop(&mut self) {
+            unsafe { std::alloc::dealloc(self.base, Self::layout(self.max_pages)) };
+        }
+    }
+
+    impl Memory for FakeMemory {
+        fn heap_base(&self) -> usize {
+            // as if some static data came first
+            self.base.addr() + 64
+        }
+
+        fn end(&self) -> usize {
+            self.base.addr() + self.pages * PAGE_SIZE
+        }
+
+        fn grow(&mut self, pages: usize) -> Option<usize> {
+            if self.pages + pages > self.max_pages {
+                return None;
+            }
+            let previous_end = self.end();
+            self.pages += pages;
+            Some(previous_end)
+        }
+    }
+
+    #[test]
+    fn test_blocks_are_aligned_to_their_class_and_reused() {
+        let mut state = State::new(FakeMemory::new(1, 1));
+        let small = Layout::from_size_align(24, 8).unwrap();
+        let aligned = Layout::from_size_align(8, 128).unwrap();
+
+        let a = state.alloc(small);
+        let b = state.alloc(aligned);
+        assert_eq!(a.addr() % 32, 0);
+        assert_eq!(b.addr() % 128, 0);
+        unsafe {
+            a.write_bytes(0xAB, 24);
+            b.write_bytes(0xCD, 8);
+            state.dealloc(a, small);
+        }
+        // any request of the same class gets the freed block back
+        assert_eq!(state.alloc(Layout::from_size_align(17, 1).unwrap()), a);
+        assert_eq!(unsafe { *b }, 0xCD);
+    }
+
+    #[test]
+    fn test_grows_memory_until_it_cannot() {
+        let mut state = State::new(FakeMemory::new(1, 3));
+        let base = state.memory.base.addr();
+        let half_page = Layout::from_size_align(PAGE_SIZE / 2, 1).unwrap();
+
+        // fits in the initial page, after the static data
+        assert_eq!(state.alloc(half_page).addr(), base + PAGE_SIZE / 2);
+        assert_eq!(state.memory.pages, 1);
+        assert_eq!(state.alloc(half_page).addr(), base + PAGE_SIZE);
+        assert_eq!(state.memory.pages, 2);
+        let page = Layout::from_size_align(PAGE_SIZE, 1).unwrap();
+        assert_eq!(state.alloc(page).addr(), base + 2 * PAGE_SIZE);
+        assert_eq!(state.memory.pages, 3);
+
+        assert!(state.alloc(Layout::new::<u64>()).is_null());
+        assert_eq!(state.memory.pages, 3);
+    }
+
+    #[test]
+    fn test_skips_pages_grown_by_someone_else() {
+        let mut state = State::new(FakeMemory::new(1, 3));
+        let base = state.memory.base.addr();
+        let half_page = Layout::from_size_align(PAGE_SIZE / 2, 1).unwrap();
+
+        assert_eq!(state.alloc(half_page).addr(), base + PAGE_SIZE / 2);
+        // e.g. JavaScript calling memory.grow
+        state.memory.pages += 1;
+
+        assert_eq!(state.alloc(half_page).addr(), base + 2 * PAGE_SIZE);
+        assert_eq!(state.memory.pages, 3);
+    }
+}
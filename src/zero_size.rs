@@ -0,0 +1,24 @@
+//! The zero-size allocation contract shared by every allocator in the crate.
+//!
+//! A zero-size request gets [`dangling`]: a non-null pointer whose address is
+//! the requested alignment. It owns no memory and never touches the heap.
+//! Freeing it is a no-op, so callers can treat it like any other block, and
+//! allocators skip it on `dealloc` with [`is_dangling`].
+
+use core::alloc::Layout;
+use core::ptr;
+
+/// The pointer returned for a zero-size `layout`.
+pub const fn dangling(layout: Layout) -> *mut u8 {
+    ptr::without_provenance_mut(layout.align())
+}
+
+/// Whether a block being freed or resized is a zero-size one handed out by
+/// [`dangling`], and so must not reach the heap.
+pub fn is_dangling(ptr: *mut u8, layout: Layout) -> bool {
+    if layout.size() != 0 {
+        return false;
+    }
+    debug_assert_eq!(ptr, dangling(layout), "zero-size block was not allocated here");
+    true
+}
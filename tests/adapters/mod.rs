@@ -0,0 +1,123 @@
+// Adapters giving established embedded allocators the same shape as ours: an
+// owned heap of a given size behind GlobalAlloc. They also apply this crate's
+// zero-size contract (dangling pointer, no heap consumed), which the upstream
+// crates don't follow, so the shared suite compares like with like.
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::ptr;
+
+use simple_alloc::zero_size;
+
+struct HeapMemory {
+    ptr: *mut u8,
+    size: usize,
+}
+
+impl HeapMemory {
+    fn new(size: usize) -> Self {
+        let memory = vec![0u8; size].into_boxed_slice();
+        Self {
+            ptr: Box::into_raw(memory).cast(),
+            size,
+        }
+    }
+}
+
+impl Drop for HeapMemory {
+    fn drop(&mut self) {
+        unsafe {
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(
+                self.ptr, self.size,
+            )))
+        };
+    }
+}
+
+pub struct LinkedListAllocator {
+    heap: linked_list_allocator::LockedHeap,
+    _memory: HeapMemory,
+}
+
+unsafe impl Send for LinkedListAllocator {}
+unsafe impl Sync for LinkedListAllocator {}
+
+impl LinkedListAllocator {
+    pub fn new(size: usize) -> Self {
+        let memory = HeapMemory::new(size);
+        let heap = unsafe { linked_list_allocator::LockedHeap::new(memory.ptr, memory.size) };
+        Self {
+            heap,
+            _memory: memory,
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for LinkedListAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == 0 {
+            return zero_size::dangling(layout);
+        }
+        unsafe { self.heap.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if !zero_size::is_dangling(ptr, layout) {
+            unsafe { self.heap.dealloc(ptr, layout) }
+        }
+    }
+}
+
+pub struct TalcAllocator {
+    talc: talc::Talck<spin::Mutex<()>, talc::ErrOnOom>,
+    _memory: HeapMemory,
+}
+
+unsafe impl Send for TalcAllocator {}
+unsafe impl Sync for TalcAllocator {}
+
+impl TalcAllocator {
+    pub fn new(size: usize) -> Self {
+        let memory = HeapMemory::new(size);
+        let talc = talc::Talc::new(talc::ErrOnOom).lock::<spin::Mutex<()>>();
+        unsafe {
+            talc.lock()
+                .claim(talc::Span::from_base_size(memory.ptr, memory.size))
+                .expect("heap too small for talc");
+        }
+        Self {
+            talc,
+            _memory: memory,
+        }
+    }
+}
+
+unsafe impl GlobalAlloc for TalcAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        if layout.size() == 0 {
+            return zero_size::dangling(layout);
+        }
+        unsafe { self.talc.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        if !zero_size::is_dangling(ptr, layout) {
+            unsafe { self.talc.dealloc(ptr, layout) }
+        }
+    }
+
+    unsafe fn realloc(&self, ptr: *mut u8, layout: Layout, new_size: usize) -> *mut u8 {
+        if layout.size() == 0 || new_size == 0 {
+            // fall back to alloc + copy + dealloc for the zero-size cases
+            let new_layout = unsafe { Layout::from_size_align_unchecked(new_size, layout.align()) };
+            let new_ptr = unsafe { self.alloc(new_layout) };
+            if !new_ptr.is_null() {
+                unsafe {
+                    new_ptr.copy_from_nonoverlapping(ptr, layout.size().min(new_size));
+                    self.dealloc(ptr, layout);
+                }
+            }
+            return new_ptr;
+        }
+        unsafe { self.talc.realloc(ptr, layout, new_size) }
+    }
+}
@@ -0,0 +1,202 @@
+// Each file under tests/ is its own crate, so each one can install a
+// different allocator as #[global_allocator] and run these tests against it.
+//
+// The first test checks that allocations really come from the allocator
+// under test. By default its heap must be inline; an allocator whose heap is
+// elsewhere passes the heap's address range as `heap = ...`.
+macro_rules! global_test_suite {
+    ($allocator:ty, $make_allocator:expr) => {
+        global_test_suite!(
+            $allocator,
+            $make_allocator,
+            heap = {
+                let heap_start = &raw const ALLOCATOR as usize;
+                heap_start..heap_start + core::mem::size_of_val(&ALLOCATOR)
+            }
+        );
+    };
+    ($allocator:ty, $make_allocator:expr, heap = $heap:expr) => {
+        #[global_allocator]
+        static ALLOCATOR: $allocator = $make_allocator;
+
+        #[test]
+        fn test_global_allocator_in_use() {
+            let b = Box::new(42u64);
+            let heap: core::ops::Range<usize> = $heap;
+            let addr = &*b as *const u64 as usize;
+            assert!(
+                heap.contains(&addr),
+                "Box was not allocated from the global allocator under test"
+            );
+        }
+
+        #[test]
+        fn test_global_with_vec() {
+            let v: Vec<i32> = (0..1000).collect();
+            assert_eq!(v.len(), 1000);
+            assert_eq!(v[999], 999);
+        }
+
+        #[test]
+        fn test_global_with_string() {
+            let s = String::from("hello world, this is a string that will trigger allocation");
+            assert!(s.contains("hello"));
+
+            let mut s2 = String::new();
+            for i in 0..100 {
+                s2.push_str(&format!("item {i} "));
+            }
+            assert!(s2.contains("item 99"));
+        }
+
+        #[test]
+        fn test_global_with_box() {
+            let b = Box::new([0u8; 4096]);
+            assert_eq!(b[0], 0);
+            assert_eq!(b[4095], 0);
+        }
+
+        #[test]
+        fn test_global_vec_grow_shrink() {
+            let mut v = Vec::new();
+
+            // grow
+            for i in 0..10_000 {
+                v.push(i);
+            }
+            assert_eq!(v.len(), 10_000);
+
+            // shrink
+            v.truncate(100);
+            v.shrink_to_fit();
+            assert_eq!(v.len(), 100);
+
+            // grow again
+            for i in 0..10_000 {
+                v.push(i);
+            }
+            assert_eq!(v.len(), 10_100);
+        }
+
+        #[test]
+        fn test_global_with_btree_map() {
+            let mut map = std::collections::BTreeMap::new();
+            for i in 0..2000 {
+                map.insert(i, i * 2);
+            }
+            // removals rebalance the tree, freeing and merging nodes
+            for i in (0..2000).step_by(2) {
+                map.remove(&i);
+            }
+            assert_eq!(map.len(), 1000);
+            let keys: Vec<_> = map.range(100..110).map(|(k, _)| *k).collect();
+            assert_eq!(keys, [101, 103, 105, 107, 109]);
+            assert_eq!(map[&1999], 3998);
+        }
+
+        #[test]
+        fn test_global_with_vec_deque() {
+            let mut deque = std::collections::VecDeque::with_capacity(8);
+            // wrap around the ring buffer, then force it to grow while wrapped
+            for i in 0..6 {
+                deque.push_back(i);
+            }
+            for _ in 0..4 {
+                deque.pop_front();
+            }
+            for i in 6..100 {
+                deque.push_back(i);
+            }
+            for i in (-10..0).rev() {
+                deque.push_front(i);
+            }
+            assert_eq!(deque.len(), 106);
+            assert!(deque.iter().copied().eq((-10..0).chain(4..100)));
+        }
+
+        #[test]
+        fn test_global_string_formatting() {
+            let s = format!("{:>8}|{:08.3}|{:?}|{:#x}", "right", 12.3456, ["a", "b"], 255);
+            assert_eq!(s, "   right|0012.346|[\"a\", \"b\"]|0xff");
+
+            let lines: Vec<String> = (0..100).map(|i| format!("{i:03}")).collect();
+            let joined = lines.join(",");
+            assert_eq!(joined.len(), 100 * 3 + 99);
+            assert!(joined.ends_with("098,099"));
+        }
+
+        #[test]
+        fn test_global_rc_cycle() {
+            use std::cell::RefCell;
+            use std::rc::{Rc, Weak};
+
+            struct Node {
+                parent: RefCell<Weak<Node>>,
+                children: RefCell<Vec<Rc<Node>>>,
+            }
+
+            let root = Rc::new(Node {
+                parent: RefCell::new(Weak::new()),
+                children: RefCell::new(Vec::new()),
+            });
+            for _ in 0..10 {
+                let child = Rc::new(Node {
+                    parent: RefCell::new(Rc::downgrade(&root)),
+                    children: RefCell::new(Vec::new()),
+                });
+                root.children.borrow_mut().push(child);
+            }
+            let child = Rc::clone(&root.children.borrow()[3]);
+            assert!(Rc::ptr_eq(&child.parent.borrow().upgrade().unwrap(), &root));
+            assert_eq!(Rc::weak_count(&root), 10);
+
+            // dropping the root frees it even though children point back
+            drop(root);
+            assert!(child.parent.borrow().upgrade().is_none());
+            assert_eq!(Rc::strong_count(&child), 1);
+
+            // a strong cycle keeps both nodes alive until it is broken
+            let a = Rc::new(RefCell::new(None::<Rc<dyn std::any::Any>>));
+            let b = Rc::new(RefCell::new(Some(Rc::clone(&a) as Rc<dyn std::any::Any>)));
+            *a.borrow_mut() = Some(Rc::clone(&b) as Rc<dyn std::any::Any>);
+            assert_eq!(Rc::strong_count(&a), 2);
+            a.borrow_mut().take();
+            assert_eq!(Rc::strong_count(&b), 1);
+        }
+
+        #[test]
+        fn test_global_arc_shared_across_threads() {
+            use std::sync::{Arc, Mutex, Weak};
+
+            struct Shared {
+                values: Mutex<Vec<u64>>,
+                this: Mutex<Weak<Shared>>,
+            }
+
+            let shared = Arc::new(Shared {
+                values: Mutex::new(Vec::new()),
+                this: Mutex::new(Weak::new()),
+            });
+            *shared.this.lock().unwrap() = Arc::downgrade(&shared);
+
+            let handles: Vec<_> = (0..4)
+                .map(|t| {
+                    let shared = Arc::clone(&shared);
+                    std::thread::spawn(move || {
+                        for i in 0..250 {
+                            shared.values.lock().unwrap().push(t * 1000 + i);
+                        }
+                    })
+                })
+                .collect();
+            for handle in handles {
+                handle.join().unwrap();
+            }
+
+            assert_eq!(shared.values.lock().unwrap().len(), 1000);
+            let weak = Arc::downgrade(&shared);
+            drop(shared);
+            assert!(weak.upgrade().is_none());
+        }
+    };
+}
@@ -0,0 +1,24 @@
+// Runs the shared conformance suite against established allocators, so
+// behavioural differences with ours show up on the same tests.
+
+mod adapters;
+
+mod linked_list_allocator_suite {
+    use super::adapters::LinkedListAllocator;
+
+    simple_alloc::test_suite! {
+        LinkedListAllocator::new(65536),
+        LinkedListAllocator::new(256)
+    }
+}
+
+mod talc_suite {
+    use super::adapters::TalcAllocator;
+
+    // talc keeps its bin table inside the heap, so it needs a bigger small
+    // heap to fit one 64-byte block
+    simple_alloc::test_suite! {
+        TalcAllocator::new(65536),
+        TalcAllocator::new(1280)
+    }
+}
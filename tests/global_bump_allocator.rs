@@ -0,0 +1,8 @@
+#[macro_use]
+mod common;
+
+use simple_alloc::BumpAllocator;
+
+const HEAP_SIZE: usize = 16 * 1024 * 1024;
+
+global_test_suite!(BumpAllocator<HEAP_SIZE>, BumpAllocator::new([0; HEAP_SIZE]));
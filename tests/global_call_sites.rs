@@ -0,0 +1,11 @@
+#[macro_use]
+mod common;
+
+use simple_alloc::{BumpAllocator, CallSites};
+
+const HEAP_SIZE: usize = 16 * 1024 * 1024;
+
+global_test_suite!(
+    CallSites<BumpAllocator<HEAP_SIZE>, 4>,
+    CallSites::new(BumpAllocator::new([0; HEAP_SIZE]))
+);
@@ -0,0 +1,50 @@
+#[macro_use]
+mod common;
+
+use simple_alloc::{AllocDisabler, BumpAllocator, forbid_alloc};
+
+const HEAP_SIZE: usize = 16 * 1024 * 1024;
+
+global_test_suite!(
+    AllocDisabler<BumpAllocator<HEAP_SIZE>>,
+    AllocDisabler::new(BumpAllocator::new([0; HEAP_SIZE]))
+);
+
+const CHILD: &str = "SIMPLE_ALLOC_FORBID_CHILD";
+
+#[test]
+fn test_no_allocation_inside_forbid_alloc() {
+    let v: Vec<i32> = (1..=3).collect();
+    let sum = forbid_alloc(|| v.iter().sum::<i32>());
+    assert_eq!(sum, 6);
+}
+
+// A violation aborts the process, so it runs in a copy of this test binary.
+#[test]
+fn test_allocation_inside_forbid_alloc_aborts() {
+    if std::env::var_os(CHILD).is_some() {
+        let b = forbid_alloc(|| Box::new(0u64));
+        unreachable!("allocated {b} inside forbid_alloc");
+    }
+
+    let output = std::process::Command::new(std::env::current_exe().unwrap())
+        .args(["test_allocation_inside_forbid_alloc_aborts", "--exact"])
+        // the harness captures output in memory, which the abort would lose
+        .arg("--nocapture")
+        .env(CHILD, "1")
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        const SIGABRT: i32 = 6;
+        assert_eq!(output.status.signal(), Some(SIGABRT));
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("allocation of 8 bytes (align 8) inside forbid_alloc"),
+        "unexpected output: {stderr}"
+    );
+}
@@ -0,0 +1,42 @@
+#[macro_use]
+mod common;
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::sync::Once;
+
+use simple_alloc::{Heap, HeapRegion, RegionStorage};
+
+const HEAP_SIZE: usize = 16 * 1024 * 1024;
+
+static STORAGE: RegionStorage<HEAP_SIZE, 4096> = RegionStorage::new();
+static REGION: HeapRegion<HEAP_SIZE, 4096> = unsafe { HeapRegion::new(&STORAGE) };
+
+/// A `Heap` is initialized at startup; the test harness allocates before any
+/// test runs, so this one does it on the first allocation.
+struct InitOnFirstUse {
+    heap: Heap,
+    init: Once,
+}
+
+unsafe impl GlobalAlloc for InitOnFirstUse {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.init.call_once(|| self.heap.init_region(&REGION));
+        unsafe { self.heap.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { self.heap.dealloc(ptr, layout) }
+    }
+}
+
+global_test_suite!(
+    InitOnFirstUse,
+    InitOnFirstUse {
+        heap: Heap::empty(),
+        init: Once::new(),
+    },
+    heap = {
+        let heap_start = &raw const STORAGE as usize;
+        heap_start..heap_start + HEAP_SIZE
+    }
+);
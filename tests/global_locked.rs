@@ -0,0 +1,42 @@
+#[macro_use]
+mod common;
+
+use std::alloc::Layout;
+use std::cell::UnsafeCell;
+use std::ptr;
+
+use simple_alloc::{Locked, UnsyncAlloc, zero_size};
+
+const HEAP_SIZE: usize = 16 * 1024 * 1024;
+
+/// A bump allocator that needs `&mut self`, so it is only usable as a global
+/// allocator through `Locked`.
+struct UnsyncBump {
+    heap: UnsafeCell<[u8; HEAP_SIZE]>,
+    next: usize,
+}
+
+impl UnsyncAlloc for UnsyncBump {
+    fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        if layout.size() == 0 {
+            return zero_size::dangling(layout);
+        }
+        let base = self.heap.get_mut().as_mut_ptr();
+        let start = (base.addr() + self.next).next_multiple_of(layout.align()) - base.addr();
+        if start + layout.size() > HEAP_SIZE {
+            return ptr::null_mut();
+        }
+        self.next = start + layout.size();
+        unsafe { base.add(start) }
+    }
+
+    unsafe fn dealloc(&mut self, _ptr: *mut u8, _layout: Layout) {}
+}
+
+global_test_suite!(
+    Locked<UnsyncBump>,
+    Locked::new(UnsyncBump {
+        heap: UnsafeCell::new([0; HEAP_SIZE]),
+        next: 0,
+    })
+);
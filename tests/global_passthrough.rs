@@ -0,0 +1,11 @@
+#[macro_use]
+mod common;
+
+use simple_alloc::{BumpAllocator, Passthrough};
+
+const HEAP_SIZE: usize = 8 * 1024 * 1024;
+
+global_test_suite!(
+    Passthrough<BumpAllocator<HEAP_SIZE>, BumpAllocator<HEAP_SIZE>, 1024>,
+    Passthrough::new(BumpAllocator::new([0; HEAP_SIZE]), BumpAllocator::new([0; HEAP_SIZE]))
+);
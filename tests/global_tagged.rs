@@ -0,0 +1,11 @@
+#[macro_use]
+mod common;
+
+use simple_alloc::{BumpAllocator, Tagged};
+
+const HEAP_SIZE: usize = 16 * 1024 * 1024;
+
+global_test_suite!(
+    Tagged<BumpAllocator<HEAP_SIZE>, 2>,
+    Tagged::new(BumpAllocator::new([0; HEAP_SIZE]))
+);
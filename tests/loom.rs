@@ -0,0 +1,59 @@
+// Model checks of the bump allocation path under every interleaving loom
+// can find. Run with:
+//
+//     RUSTFLAGS="--cfg loom" cargo test --release --test loom
+#![cfg(loom)]
+
+use std::alloc::{GlobalAlloc, Layout};
+
+use loom::sync::Arc;
+use loom::thread;
+use simple_alloc::BumpAllocator;
+
+fn assert_disjoint(mut blocks: Vec<(usize, usize)>) {
+    blocks.sort_unstable();
+    for pair in blocks.windows(2) {
+        let ((a, a_len), (b, _)) = (pair[0], pair[1]);
+        assert!(a + a_len <= b, "blocks at {a:#x} and {b:#x} overlap");
+    }
+}
+
+#[test]
+fn test_concurrent_allocations_are_disjoint() {
+    loom::model(|| {
+        let allocator = Arc::new(BumpAllocator::new([0; 64]));
+        let layout = Layout::from_size_align(16, 8).unwrap();
+
+        let handles: Vec<_> = (0..2)
+            .map(|_| {
+                let allocator = allocator.clone();
+                thread::spawn(move || unsafe { allocator.alloc(layout) }.addr())
+            })
+            .collect();
+        let mut blocks = vec![unsafe { allocator.alloc(layout) }.addr()];
+        blocks.extend(handles.into_iter().map(|h| h.join().unwrap()));
+
+        assert!(blocks.iter().all(|&addr| addr != 0));
+        assert_disjoint(blocks.into_iter().map(|addr| (addr, 16)).collect());
+    });
+}
+
+#[test]
+fn test_extend_in_place_races_with_alloc() {
+    loom::model(|| {
+        let allocator = Arc::new(BumpAllocator::new([0; 64]));
+        let layout = Layout::from_size_align(16, 8).unwrap();
+        let first = unsafe { allocator.alloc(layout) };
+
+        let other = {
+            let allocator = allocator.clone();
+            thread::spawn(move || unsafe { allocator.alloc(layout) }.addr())
+        };
+        let extended = unsafe { allocator.extend_in_place(first, layout, 16) };
+        let other = other.join().unwrap();
+
+        assert_ne!(other, 0);
+        let first_len = if extended { 32 } else { 16 };
+        assert_disjoint(vec![(first.addr(), first_len), (other, 16)]);
+    });
+}
@@ -0,0 +1,38 @@
+// OomPolicy::Panic aborts the process, so the failing allocation runs in a
+// copy of this test binary.
+
+use std::alloc::{GlobalAlloc, Layout};
+use std::process::Command;
+
+use simple_alloc::{BumpAllocator, OomPolicy};
+
+const CHILD: &str = "SIMPLE_ALLOC_OOM_CHILD";
+
+#[test]
+fn test_oom_policy_panic_aborts() {
+    if std::env::var_os(CHILD).is_some() {
+        let allocator = BumpAllocator::new([0; 256]).with_oom_policy(OomPolicy::Panic);
+        unsafe { allocator.alloc(Layout::from_size_align(512, 8).unwrap()) };
+        unreachable!("allocation returned");
+    }
+
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args(["test_oom_policy_panic_aborts", "--exact", "--nocapture"])
+        .env(CHILD, "1")
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        const SIGABRT: i32 = 6;
+        assert_eq!(output.status.signal(), Some(SIGABRT));
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr
+            .contains("out of memory: failed to allocate 512 bytes (align 8), 0/256 bytes in use"),
+        "unexpected output: {stderr}"
+    );
+}
@@ -0,0 +1,44 @@
+// A corrupted pool aborts the process, so the corrupting code runs in a copy
+// of this test binary.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::process::Command;
+
+use simple_alloc::UnsyncPoolResource;
+
+const CHILD: &str = "SIMPLE_ALLOC_POOL_CHECKSUM_CHILD";
+
+#[test]
+fn test_corrupted_free_list_aborts() {
+    if std::env::var_os(CHILD).is_some() {
+        let pool = UnsyncPoolResource::new(&System);
+        let layout = Layout::from_size_align(64, 8).unwrap();
+        unsafe {
+            let ptr = pool.alloc(layout);
+            pool.dealloc(ptr, layout);
+            // a write after free, over the link
+            ptr.cast::<usize>().write(0xDEAD_BEEF);
+            pool.alloc(layout);
+        }
+        unreachable!("corruption went unnoticed");
+    }
+
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args(["test_corrupted_free_list_aborts", "--exact", "--nocapture"])
+        .env(CHILD, "1")
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        const SIGABRT: i32 = 6;
+        assert_eq!(output.status.signal(), Some(SIGABRT));
+    }
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("pool free list corrupted at"),
+        "unexpected output: {stderr}"
+    );
+}